@@ -1,9 +1,13 @@
 use std::{
-    collections::HashMap,
+    cell::{Cell, RefCell},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
     io::ErrorKind,
+    panic::AssertUnwindSafe,
     path::{Path, PathBuf},
     rc::Rc,
     thread,
+    time::{Duration, Instant},
 };
 
 use abi_stable::{
@@ -14,34 +18,180 @@ use abi_stable::{
         RString,
     },
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dynisland_core::{
     abi::{
-        abi_stable, gdk, glib,
+        abi_stable, gdk, glib, gtk_layer_shell,
         layout::LayoutManagerType,
         log,
         module::{ActivityIdentifier, ModuleType, UIServerCommand},
     },
-    graphics::activity_widget::boxed_activity_mode::ActivityMode,
+    graphics::activity_widget::{boxed_activity_mode::ActivityMode, ActivityWidget},
     ron,
 };
+use glib::SourceId;
 use gtk::{prelude::*, CssProvider, Widget};
+use gtk_layer_shell::LayerShell;
+use nix::sys::signal::Signal;
 use notify::{RecommendedWatcher, Watcher};
-use ron::{extensions::Extensions, ser::PrettyConfig};
+use ron::{ser::PrettyConfig, Value};
+use serde::Serialize;
 use tokio::sync::{mpsc::unbounded_channel, Mutex};
 
 use crate::{
-    config::{self, Config, GeneralConfig},
-    ipc::open_socket,
-    layout_manager::{self, fallback_layout},
+    config::{self, Config, GeneralConfig, ModuleEntry, X11WindowType},
+    dbus,
+    ipc::{self, open_socket},
+    layout_manager::{self, fallback_layout, flow_layout},
+    module_loading::module_log_target,
 };
 
+/// The base style, loaded below the user's scss
+pub const DEFAULT_SCSS: &str = include_str!("../default.scss");
+
+/// Files in the config dir that replace `DEFAULT_SCSS`, in order of preference. `.css` files aren't compiled
+const FALLBACK_FILES: [&str; 3] = ["fallback.scss", "base.scss", "default.css"];
+
+/// Written at startup and removed when the windows are created, if it's still there the last start crashed
+pub const STARTING_MARKER: &str = "starting";
+
+/// How long `hide_until_first_activity` keeps the windows hidden if no activity is added
+const FIRST_ACTIVITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Producer restarts within this time after a reload are counted as a crash loop
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(10);
+/// Number of producer restarts in `CRASH_LOOP_WINDOW` that reverts the config
+const CRASH_LOOP_RESTARTS: usize = 3;
+/// Restarts of a module's producers within this time are counted for the backoff
+const PRODUCER_RESTART_WINDOW: Duration = Duration::from_secs(60);
+/// Number of producer restarts in `PRODUCER_RESTART_WINDOW` that disables the restarts until the next reload
+const PRODUCER_MAX_RESTARTS: usize = 5;
+/// Delay before the second restart in the window, doubled for each of the next ones
+const PRODUCER_BACKOFF_BASE: Duration = Duration::from_millis(500);
+/// Config reloads requested within this time are applied once, editors that autosave send one per save
+pub(crate) const RELOAD_DEBOUNCE: Duration = Duration::from_millis(150);
+
+const LOADED_MODULES_DOC: &str = "    // modules can also be loaded only if all the specified checks pass:
+    // (name: \"module-name\", condition: (env: \"VAR_NAME\", file_exists: \"/path/to/file\", hostname: \"my-pc\")),
+    loaded_modules: ";
+
+/// Removals scheduled by `remove_debounce_ms` that can still be cancelled by re-adding the activity
+pub type PendingRemovals = Rc<RefCell<HashMap<ActivityIdentifier, SourceId>>>;
+
+/// Module name -> times its producers were restarted by `RestartProducers`,
+/// `None` once the restarts are disabled
+pub type ProducerRestarts = Rc<RefCell<HashMap<String, Option<VecDeque<Instant>>>>>;
+
+/// Runtime counters, returned as json by the `stats` command
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Stats {
+    pub config_reloads: u64,
+    pub css_reloads: u64,
+    pub last_css_compile_ms: Option<f64>,
+    pub registered_activities: usize,
+    pub prefers_dark: Option<bool>,
+    pub ui_queue_high_water_mark: usize,
+    pub layout_profile: Option<String>,
+    pub activity_filter: Option<String>,
+    pub capabilities: Option<Capabilities>,
+}
+
+/// What the display supports, detected once the first window has a renderer
+#[derive(Debug, Serialize, Clone)]
+pub struct Capabilities {
+    /// `wayland`, `x11` or the name of the gdk display type
+    pub backend: String,
+    pub layer_shell: bool,
+    pub renderer: String,
+    /// `layer-shell`, `x11-hints` or `normal`
+    pub window_mode: String,
+}
+
+impl Capabilities {
+    fn detect(display: &gdk::Display, renderer: &str, x11_window_type: X11WindowType) -> Self {
+        let backend = if display.is::<gdk_x11::X11Display>() {
+            "x11".to_string()
+        } else {
+            match display.type_().name() {
+                "GdkWaylandDisplay" => "wayland".to_string(),
+                name => name.to_string(),
+            }
+        };
+        let layer_shell = layout_manager::window_position::layer_shell_supported();
+        let window_mode = if layer_shell {
+            "layer-shell"
+        } else if backend == "x11" && x11_window_type != X11WindowType::Normal {
+            "x11-hints"
+        } else {
+            "normal"
+        };
+        Self {
+            backend,
+            layer_shell,
+            renderer: renderer.to_string(),
+            window_mode: window_mode.to_string(),
+        }
+    }
+
+    fn log(&self) {
+        log::info!(
+            "capabilities: backend={} layer_shell={} renderer={} window_mode={}",
+            self.backend,
+            self.layer_shell,
+            self.renderer,
+            self.window_mode
+        );
+        if self.renderer == "GskCairoRenderer" {
+            log::warn!("using the software renderer, animations and blur may be slow");
+        }
+    }
+}
+
+/// An activity in `list-activities --json`
+#[derive(Debug, Serialize, Clone)]
+pub struct ActivityState {
+    pub activity: String,
+    pub visible: bool,
+    pub mode: String,
+    pub classes: Vec<String>,
+}
+
+/// Settings for `daemon --once`
+#[derive(Debug, Clone)]
+pub struct OnceMode {
+    /// time given to the modules to add their activities
+    pub delay: Duration,
+    pub screenshot: Option<PathBuf>,
+}
+
 pub enum BackendServerCommand {
-    ReloadConfig,
+    /// `respond` is set when the sender waits for the errors as a response,
+    /// `force` applies every section and restarts the producers even if they didn't change
+    ReloadConfig {
+        respond: bool,
+        force: bool,
+    },
+    ReloadCss,
     Stop,
     OpenInspector,
     ActivityNotification(ActivityIdentifier, ActivityMode, Option<u64>),
-    ListActivities,
+    SetActivityVisible(ActivityIdentifier, bool),
+    SetActivityClasses(ActivityIdentifier, Vec<String>),
+    /// glob pattern of the activities to keep visible, `None` shows the ones hidden by the filter
+    FilterActivities(Option<String>),
+    GetGeneral(String),
+    /// field and json value
+    SetGeneral(String, String),
+    /// true if the settings portal prefers a dark color scheme
+    ColorSchemeChanged(bool),
+    /// `None` goes back to the base layout config
+    SetLayoutProfile(Option<String>),
+    /// true to respond with the state of the activities as json
+    ListActivities(bool),
+    Stats,
+    DumpCss,
+    /// go back to the last known good config, sent when the producers crash after a reload
+    RevertConfig,
     ModuleCliCommand(String, String),
     LayoutCliCommand(String),
 }
@@ -56,14 +206,201 @@ pub struct App {
     pub app_send: Option<RSender<UIServerCommand>>,
     pub config: Config,
     pub css_provider: CssProvider,
+    pub fallback_provider: CssProvider,
+    /// css generated from the general config, between the fallback and the user's css
+    pub generated_provider: CssProvider,
+    /// shared with the windows' motion controllers so reloads apply to them
+    pub inactive_delay: Rc<Cell<Duration>>,
+    /// shared with the windows' realize handlers, so the windows created later are kept above too
+    pub keep_above: Rc<Cell<bool>>,
+    /// shared with the UI loop, so the commands use the config set by the last reload or `set-general`
+    pub general_config: Rc<RefCell<GeneralConfig>>,
+    /// true while `hide_until_first_activity` keeps the windows hidden
+    pub waiting_first_activity: Rc<Cell<bool>>,
+    /// shared with the windows' realize handlers, like `keep_above`
+    pub x11_window_type: Rc<Cell<X11WindowType>>,
+    /// shared with the UI loop, which checks the sizes after adding an activity
+    pub warn_size_issues: Rc<Cell<bool>>,
+    /// last color scheme received from the settings portal
+    pub prefers_dark: Option<bool>,
+    /// the last css that compiled successfully, it's what the css provider contains
+    pub last_css: String,
+    /// true if the last scss compile failed
+    pub css_stale: bool,
     pub config_dir: PathBuf,
+    pub open_inspector: bool,
+    /// ignore the user's config and scss
+    pub safe_mode: bool,
+    pub profile: Option<String>,
+    /// layout config profile selected with `set-layout-profile`
+    pub layout_profile: Option<String>,
+    pub once: Option<OnceMode>,
+    pub stats: Rc<RefCell<Stats>>,
+    /// classes set with `set-activity-classes`, removed when they are replaced
+    pub activity_classes: HashMap<ActivityIdentifier, Vec<String>>,
+    /// the config that was running before the last reload, if it loaded without errors
+    pub last_good_config: Option<Config>,
+    /// false if the last reload had errors
+    pub config_ok: bool,
+    /// time of the last reload, shared with the UI loop to detect producer crash loops
+    pub last_reload: Rc<Cell<Option<Instant>>>,
+    /// recent producer restarts of each module, cleared on reload
+    pub producer_restarts: ProducerRestarts,
+    /// hash of the scss files and compile options of `last_css`, see [`hash_scss_sources`]
+    pub css_hash: Option<u64>,
+    /// the only module loaded by `dynisland dev`, the rest of the config is ignored
+    pub dev_module: Option<String>,
+    /// pattern set with `filter-activities`
+    pub activity_filter: Option<String>,
+    /// activities hidden by `activity_filter`, the ones hidden with `set-activity-visible` aren't here
+    pub filter_hidden: HashSet<ActivityIdentifier>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RestartDecision {
+    Now,
+    After(Duration),
+    Disabled,
+}
+
+/// Records a restart request of the module's producers and decides when it can happen.
+///
+/// The first restart in `PRODUCER_RESTART_WINDOW` is immediate, the next ones wait
+/// `PRODUCER_BACKOFF_BASE` doubled every time, after `PRODUCER_MAX_RESTARTS` they are refused
+fn producer_restart_decision(restarts: &ProducerRestarts, module_name: &str) -> RestartDecision {
+    let mut restarts = restarts.borrow_mut();
+    let Some(times) = restarts
+        .entry(module_name.to_string())
+        .or_insert_with(|| Some(VecDeque::new()))
+    else {
+        return RestartDecision::Disabled;
+    };
+    let now = Instant::now();
+    times.retain(|time| now.duration_since(*time) < PRODUCER_RESTART_WINDOW);
+    times.push_back(now);
+    match times.len() {
+        1 => RestartDecision::Now,
+        count if count > PRODUCER_MAX_RESTARTS => {
+            log::error!(
+                "the producers of {module_name} were restarted {PRODUCER_MAX_RESTARTS} times in {PRODUCER_RESTART_WINDOW:?}, \
+                 they won't be restarted again until the config is reloaded"
+            );
+            restarts.insert(module_name.to_string(), None);
+            RestartDecision::Disabled
+        }
+        count => RestartDecision::After(PRODUCER_BACKOFF_BASE * 2u32.pow(count as u32 - 2)),
+    }
+}
+
+/// Shows or hides the activity, returning the error response if it's not in the layout
+async fn set_activity_visible(
+    layout: &Mutex<(String, LayoutManagerType)>,
+    id: &ActivityIdentifier,
+    visible: bool,
+) -> Option<String> {
+    match layout.lock().await.1.get_activity(id).into_option() {
+        Some(activity) => {
+            let activity: Widget = activity.try_into().unwrap();
+            // the widget stays in the layout, so its state and producers are kept
+            activity.set_visible(visible);
+            None
+        }
+        None => Some(format!("activity not found: {id}")),
+    }
+}
+
+/// Replaces the classes the last call set on the activity with `classes`, the other classes are kept.
+/// Returns the error response if the activity is not in the layout
+async fn set_activity_classes(
+    layout: &Mutex<(String, LayoutManagerType)>,
+    activity_classes: &mut HashMap<ActivityIdentifier, Vec<String>>,
+    id: ActivityIdentifier,
+    classes: Vec<String>,
+) -> Option<String> {
+    match layout.lock().await.1.get_activity(&id).into_option() {
+        Some(activity) => {
+            let activity: Widget = activity.try_into().unwrap();
+            for class in activity_classes.remove(&id).unwrap_or_default() {
+                activity.remove_css_class(&class);
+            }
+            for class in classes.iter() {
+                activity.add_css_class(class);
+            }
+            activity_classes.insert(id, classes);
+            None
+        }
+        None => Some(format!("activity not found: {id}")),
+    }
+}
+
+/// Applies the filter `pattern` to the activities, with their current visibility.
+///
+/// Returns the activities hidden by the filter, which are hidden again if another command showed them,
+/// and the ones it hid before that it shows again. The activities hidden by other commands stay hidden
+fn filter_changes(
+    activities: &[(ActivityIdentifier, bool)],
+    pattern: Option<&glob::Pattern>,
+    previously_hidden: &HashSet<ActivityIdentifier>,
+) -> (HashSet<ActivityIdentifier>, HashSet<ActivityIdentifier>) {
+    let mut hidden = HashSet::new();
+    let mut shown = HashSet::new();
+    for (id, visible) in activities {
+        let matches = pattern.map_or(true, |pattern| pattern.matches(&id.to_string()));
+        let hidden_by_filter = previously_hidden.contains(id);
+        if !matches && (*visible || hidden_by_filter) {
+            hidden.insert(id.clone());
+        } else if matches && hidden_by_filter {
+            shown.insert(id.clone());
+        }
+    }
+    (hidden, shown)
+}
+
+/// Records a producer restart at `now` and checks if it makes `CRASH_LOOP_RESTARTS` in `CRASH_LOOP_WINDOW`
+/// after the reload, clearing the restarts if it does
+fn is_crash_loop(restarts: &mut VecDeque<Instant>, last_reload: Instant, now: Instant) -> bool {
+    restarts.retain(|time| now.duration_since(*time) < CRASH_LOOP_WINDOW);
+    if now.duration_since(last_reload) < CRASH_LOOP_WINDOW {
+        restarts.push_back(now);
+    }
+    if restarts.len() >= CRASH_LOOP_RESTARTS {
+        restarts.clear();
+        return true;
+    }
+    false
 }
 
 impl App {
     pub fn run(mut self, config_dir: &Path) -> Result<()> {
-        self.config = config::get_config(config_dir);
+        self.config = self.dev_config(config::get_config(config_dir, self.profile.as_deref()));
         self.config_dir = config_dir.to_path_buf();
 
+        let starting_marker = self.config.get_runtime_dir().join(STARTING_MARKER);
+        // a module crashing during development shouldn't put the next normal start in safe mode
+        if self.dev_module.is_some() {
+            log::info!("dev mode, the config is restricted to one module");
+        } else if !self.safe_mode && starting_marker.exists() {
+            log::error!(
+                "the last start didn't finish, starting in safe mode with the default config and css. \
+                 Fix the config and run `dynisland restart` to start normally"
+            );
+            self.safe_mode = true;
+        } else if self.safe_mode {
+            log::warn!("starting in safe mode with the default config and css");
+        }
+        if self.safe_mode {
+            // keep the runtime dir, so the socket is where the cli expects it
+            self.config = Config {
+                debug: self.config.debug.clone(),
+                ..Config::default()
+            };
+        }
+        if self.dev_module.is_none() {
+            if let Err(err) = std::fs::write(&starting_marker, std::process::id().to_string()) {
+                log::warn!("failed to write {}: {err}", starting_marker.display());
+            }
+        }
+
         let (server_send, server_recv) = unbounded_channel::<BackendServerCommand>();
         let (server_response_send, server_response_recv) = unbounded_channel::<Option<String>>();
         let runtime_path = self.config.get_runtime_dir();
@@ -75,98 +412,169 @@ impl App {
         self.load_layout_config();
 
         let module_order = self.load_modules(config_dir);
+        if let (Some(module_name), true) = (&self.dev_module, module_order.is_empty()) {
+            anyhow::bail!("module {module_name} couldn't be loaded");
+        }
         self.load_configs(config_dir);
         self.init_loaded_modules(&module_order);
 
         // init layout manager and send start signal
         let (start_signal_tx, start_signal_rx) = tokio::sync::broadcast::channel::<()>(1);
-        let open_debugger = self
-            .config
-            .debug
-            .clone()
-            .map(|d| d.open_debugger_at_start)
-            .unwrap_or(false);
+        let open_debugger = self.open_inspector
+            || self
+                .config
+                .debug
+                .clone()
+                .map(|d| d.open_debugger_at_start)
+                .unwrap_or(false);
+        let x11_window_type = self.config.general_style_config.x11_window_type;
+        let keep_above = self.config.general_style_config.keep_above;
+        // the built-in layouts read it when they configure a window
+        layout_manager::window_position::set_keep_above(keep_above);
+        self.keep_above.set(keep_above);
+        let icon_theme = self.config.general_style_config.icon_theme.clone();
+        let wait_for_display = self.config.general_style_config.wait_for_display;
+        let startup_delay =
+            Duration::from_millis(self.config.general_style_config.startup_delay_ms);
+        self.inactive_delay.set(Duration::from_millis(
+            self.config.general_style_config.inactive_delay_ms,
+        ));
+        self.warn_size_issues
+            .set(self.config.general_style_config.warn_size_issues);
+        let inactive_delay = self.inactive_delay.clone();
+        let keep_above_windows = self.keep_above.clone();
+        self.x11_window_type.set(x11_window_type);
+        let x11_window_type_windows = self.x11_window_type.clone();
+        self.application.connect_window_added(move |_, window| {
+            setup_inactive_class(window, inactive_delay.clone());
+            setup_keep_above(window, keep_above_windows.clone());
+            setup_x11_window_type(window, x11_window_type_windows.clone());
+        });
+        self.waiting_first_activity
+            .set(self.config.general_style_config.hide_until_first_activity);
+        let waiting_first_activity = self.waiting_first_activity.clone();
         let layout = self.layout.clone().unwrap();
-        self.application.connect_activate(move |_app| {
-            log::info!("Loading LayoutManager");
-            layout.blocking_lock().1.init();
-            start_signal_tx.send(()).unwrap();
-            gtk::Window::set_interactive_debugging(open_debugger);
+        let activate_waiting_first_activity = waiting_first_activity.clone();
+        self.application.connect_activate(move |app| {
+            let layout = layout.clone();
+            let waiting_first_activity = activate_waiting_first_activity.clone();
+            let start_signal_tx = start_signal_tx.clone();
+            // the application would quit if it has no windows when this returns
+            let hold_guard = app.hold();
+            let app = app.clone();
+            let starting_marker = starting_marker.clone();
+            run_when_ready(wait_for_display, startup_delay, move || {
+                log::info!("Loading LayoutManager");
+                layout.blocking_lock().1.init();
+                apply_x11_window_type(&app, x11_window_type);
+                apply_keep_above(&app, keep_above);
+                apply_icon_theme(icon_theme.as_deref());
+                if waiting_first_activity.get() {
+                    // the windows are hidden before their first frame is drawn
+                    for window in app.windows() {
+                        window.set_visible(false);
+                    }
+                    let app = app.clone();
+                    glib::timeout_add_local_once(FIRST_ACTIVITY_TIMEOUT, move || {
+                        if waiting_first_activity.replace(false) {
+                            log::warn!(
+                                "no activity was added in {FIRST_ACTIVITY_TIMEOUT:?}, showing the windows anyway"
+                            );
+                            show_windows(&app);
+                        }
+                    });
+                }
+                let _ = std::fs::remove_file(&starting_marker);
+                start_signal_tx.send(()).unwrap();
+                gtk::Window::set_interactive_debugging(open_debugger);
+                drop(hold_guard);
+            });
         });
 
         //UI command consumer
         let mut start_signal = start_signal_rx.resubscribe();
         let layout = self.layout.clone().unwrap();
         let module_map = self.module_map.clone();
+        *self.general_config.borrow_mut() = self.config.general_style_config.clone();
+        let general_config = self.general_config.clone();
+        let pending_removals: PendingRemovals = Rc::new(RefCell::new(HashMap::new()));
+        let stats = self.stats.clone();
+        let ui_app = self.application.clone();
+        let last_reload = self.last_reload.clone();
+        let ui_server_send = server_send.clone();
+        let warn_size_issues = self.warn_size_issues.clone();
+        let mut producer_restarts = VecDeque::<Instant>::new();
+        let restart_backoff = self.producer_restarts.clone();
         glib::MainContext::default().spawn_local(async move {
             start_signal.recv().await.unwrap();
 
             // TODO check if there are too many tasks on the UI thread and it begins to lag
             while let Some(command) = app_recv_async.recv().await {
-                match command {
-                    UIServerCommand::AddActivity{activity_id, widget} => {
-                        let activity: Widget = match widget.try_into() {
-                            Ok(act) => {act},
-                            Err(err) => {
-                                log::error!("error while converting SabiWidget to Widget, maybe it was deallocated after UIServerCommand::AddActivity was sent: {err:#?}");
-                                continue;
-                            },
-                        };
-
-                        Self::update_general_configs_on_activity(
-                            &self.config.general_style_config,
-                            &activity,
-                        );
-
-                        if layout
-                            .lock()
-                            .await
-                            .1
-                            .get_activity(&activity_id)
-                            .is_some()
-                        {
-                            log::debug!("activity already registered on {}", activity_id.module());
-                            continue;
-                        }
-
-                        layout
-                            .lock()
-                            .await
-                            .1
-                            .add_activity(&activity_id, activity.into());
-                        log::info!("registered activity on {}", activity_id.module());
-                    }
-                    UIServerCommand::RemoveActivity { activity_id } => {
-                        let mut layout = layout.lock().await;
-                        if layout.1.get_activity(&activity_id).is_some(){
-                            layout.1.remove_activity(&activity_id);
-                            log::info!("unregistered activity on {}", activity_id.module());
-                        }else{
-                            log::warn!("error removing activity, not found: {:?}", activity_id);
-                        }
-                    }
-                    UIServerCommand::RestartProducers { module_name } => {
-                        if let Some(module) = module_map.lock().await.get(module_name.as_str()) {
-                            module.restart_producers();
+                {
+                    let queued = app_recv_async.len() + 1;
+                    let mut stats = stats.borrow_mut();
+                    stats.ui_queue_high_water_mark = stats.ui_queue_high_water_mark.max(queued);
+                }
+                let is_add = matches!(command, UIServerCommand::AddActivity { .. });
+                if matches!(command, UIServerCommand::RestartProducers { .. }) {
+                    if let Some(reload) = last_reload.get() {
+                        if is_crash_loop(&mut producer_restarts, reload, Instant::now()) {
+                            last_reload.set(None);
+                            let _ = ui_server_send.send(BackendServerCommand::RevertConfig);
                         }
                     }
-                    UIServerCommand::RequestNotification { activity_id, mode, duration} => {
-                        if mode>3{
+                }
+                if let UIServerCommand::RestartProducers { module_name } = &command {
+                    match producer_restart_decision(&restart_backoff, module_name) {
+                        RestartDecision::Now => {}
+                        RestartDecision::After(delay) => {
+                            log::warn!(
+                                target: &module_log_target(module_name),
+                                "restarting the producers of {module_name} again in {delay:?}"
+                            );
+                            let module_map = module_map.clone();
+                            let module_name = module_name.to_string();
+                            glib::timeout_add_local_once(delay, move || {
+                                // blocking here would deadlock against a task on the main thread holding the modules
+                                glib::MainContext::default().spawn_local(async move {
+                                    if let Some(module) = module_map.lock().await.get(&module_name) {
+                                        module.restart_producers();
+                                    }
+                                });
+                            });
                             continue;
                         }
-                        let layout = layout.lock().await;
-                        if layout.1.get_activity(&activity_id).is_none(){
+                        RestartDecision::Disabled => {
+                            log::debug!(target: &module_log_target(module_name), "ignoring the producer restart of {module_name}");
                             continue;
                         }
-                        layout.1.activity_notification(&activity_id, mode, duration);
                     }
                 }
+                // cloned, a reload can change it while the command is waiting for a lock
+                let config = general_config.borrow().clone();
+                handle_ui_command(command, &module_map, &layout, &config, &pending_removals).await;
+                if is_add && waiting_first_activity.replace(false) {
+                    log::debug!("first activity added, showing the windows");
+                    show_windows(&ui_app);
+                }
+                if is_add && warn_size_issues.get() {
+                    check_size_issues_when_idle(layout.clone());
+                }
             }
         });
 
         let app = self.application.clone();
+        let dbus_enabled = self.config.general_style_config.dbus;
+        let follow_color_scheme = self.config.general_style_config.follow_color_scheme;
+        // with --config-stdin there is no config file to watch
+        let watch_config =
+            self.config.general_style_config.watch_config && !config::config_from_stdin();
+        let watcher_ignore_patterns = self.config.general_style_config.ignore_patterns.clone();
+        let reload_timeout = ipc::reload_timeout(&self.config.general_style_config);
         let mut start_signal = start_signal_rx.resubscribe();
         let conf_dir = config_dir.to_path_buf();
+        let once_failed = Rc::new(Cell::new(false));
+        let once_failed_clone = once_failed.clone();
         //server command consumer
         glib::MainContext::default().spawn_local(async move {
             start_signal.recv().await.unwrap();
@@ -181,46 +589,126 @@ impl App {
             };
 
             log::info!("Using renderer: {}", renderer_name);
+            let capabilities = Capabilities::detect(
+                &gdk::Display::default().unwrap(),
+                renderer_name,
+                self.config.general_style_config.x11_window_type,
+            );
+            capabilities.log();
+            self.stats.borrow_mut().capabilities = Some(capabilities);
 
             //init css providers
-            let fallback_provider = gtk::CssProvider::new();
-            let css =
-                grass::from_string(include_str!("../default.scss"), &grass::Options::default())
-                    .unwrap();
-            fallback_provider.load_from_string(&css);
             gtk::style_context_add_provider_for_display(
                 &gdk::Display::default().unwrap(),
-                &fallback_provider,
+                &self.fallback_provider,
+                gtk::STYLE_PROVIDER_PRIORITY_SETTINGS,
+            );
+            // same priority, added later so it overrides the fallback
+            gtk::style_context_add_provider_for_display(
+                &gdk::Display::default().unwrap(),
+                &self.generated_provider,
                 gtk::STYLE_PROVIDER_PRIORITY_SETTINGS,
             );
 
             gtk::style_context_add_provider_for_display(
                 &gdk::Display::default().unwrap(),
                 &self.css_provider,
-                gtk::STYLE_PROVIDER_PRIORITY_USER,
+                self.config.general_style_config.css_priority.map_gtk(),
             );
-            self.load_css(&conf_dir); //load user's scss
+            let _ = self.load_css(&conf_dir).await; //load fallback and user's scss
 
+            if self.config.general_style_config.defer_producers_until_shown {
+                let first_window = self.application.windows().first().cloned();
+                if let Some(window) = first_window {
+                    next_frame(&window).await;
+                }
+                log::debug!("first frame drawn, starting producers");
+            }
             self.restart_producer_runtimes(); // start producers
 
+            if self.config.general_style_config.warn_size_issues {
+                check_size_issues_when_idle(self.layout.clone().unwrap());
+            }
+
+            if let Some(once) = self.once.clone() {
+                let app = self.application.clone();
+                glib::timeout_add_local_once(once.delay, move || {
+                    render_once(&app, once.screenshot, once_failed_clone);
+                });
+            }
+
             self.start_backend_server(server_recv, server_response_send, conf_dir)
                 .await;
         });
 
-        let _wathcer = start_config_dir_watcher(server_send.clone(), &config_dir);
+        // kept alive until the app exits, dropping it stops the watcher
+        let _watcher = if watch_config {
+            Some(start_config_dir_watcher(
+                server_send.clone(),
+                &config_dir,
+                &watcher_ignore_patterns,
+            ))
+        } else {
+            log::info!("config watcher disabled, reload with SIGUSR1 or `dynisland reload`");
+            None
+        };
 
         //start application
         app.register(None as Option<&gtk::gio::Cancellable>)?;
         let running = app.is_remote();
         if running {
             log::error!("dynisland is already running");
+            let _ = std::fs::remove_file(runtime_path.join(STARTING_MARKER));
         } else {
-            start_ipc_server(runtime_path.clone(), server_send, server_response_recv);
+            if dbus_enabled {
+                if let Err(err) = dbus::register_object(&app, server_send.clone()) {
+                    log::error!("failed to export DBus interface: {err}");
+                }
+            }
+            if follow_color_scheme {
+                if let Err(err) = dbus::watch_color_scheme(&app, server_send.clone()) {
+                    log::error!("failed to watch the color scheme: {err}");
+                }
+            }
+            // quit through the main loop so the socket gets removed
+            for signal in [Signal::SIGTERM, Signal::SIGINT] {
+                let server_send = server_send.clone();
+                glib::unix_signal_add_local(signal as i32, move || {
+                    log::info!("received {signal}, quitting");
+                    let _ = server_send.send(BackendServerCommand::Stop);
+                    glib::ControlFlow::Continue
+                });
+            }
+            // like `dynisland reload`, without the socket
+            let reload_signals: [(Signal, fn() -> BackendServerCommand); 2] = [
+                (Signal::SIGUSR1, || BackendServerCommand::ReloadConfig {
+                    respond: false,
+                    force: false,
+                }),
+                (Signal::SIGUSR2, || BackendServerCommand::ReloadCss),
+            ];
+            for (signal, command) in reload_signals {
+                let server_send = server_send.clone();
+                glib::unix_signal_add_local(signal as i32, move || {
+                    log::info!("received {signal}, reloading");
+                    let _ = server_send.send(command());
+                    glib::ControlFlow::Continue
+                });
+            }
+            start_ipc_server(
+                runtime_path.clone(),
+                server_send,
+                server_response_recv,
+                reload_timeout,
+            );
         }
         app.run_with_args::<String>(&[]);
         if !running {
             std::fs::remove_file(runtime_path.join("dynisland.sock"))?;
         }
+        if once_failed.get() {
+            anyhow::bail!("failed to render once");
+        }
         Ok(())
     }
 
@@ -248,20 +736,136 @@ impl App {
         server_response_send: tokio::sync::mpsc::UnboundedSender<Option<String>>,
         config_dir: std::path::PathBuf,
     ) {
-        while let Some(command) = server_recv.recv().await {
+        // commands received while coalescing css reloads
+        let mut deferred = VecDeque::new();
+        loop {
+            let command = match deferred.pop_front() {
+                Some(command) => command,
+                None => match server_recv.recv().await {
+                    Some(command) => command,
+                    None => break,
+                },
+            };
             match command {
-                BackendServerCommand::ReloadConfig => {
-                    log::info!("Reloading Config");
-                    //TODO split config and css reload (producers don't need to be restarted if only css changed)
-
+                BackendServerCommand::ReloadConfig { respond, .. } if self.safe_mode => {
+                    log::warn!("ignoring config reload in safe mode");
+                    if respond {
+                        let _ = server_response_send.send(Some(
+                            "Error:\nsafe mode, restart dynisland to load the config".to_string(),
+                        ));
+                    }
+                }
+                BackendServerCommand::ReloadConfig { respond, force } => {
                     // without this sleep, reading the config file sometimes gives an empty file.
-                    glib::timeout_future(std::time::Duration::from_millis(50)).await;
-                    self.load_configs(&config_dir);
-                    self.update_general_configs();
-                    self.load_layout_config();
-                    self.load_css(&config_dir);
+                    glib::timeout_future(RELOAD_DEBOUNCE).await;
+                    let (skipped, queued_responses, queued_force) =
+                        coalesce_config_reloads(&mut server_recv, &mut deferred);
+                    if skipped > 0 {
+                        log::debug!("coalesced {skipped} config reloads");
+                    }
+                    let force = force || queued_force;
+                    let responses = usize::from(respond) + queued_responses;
+                    let (config, mut errors) =
+                        config::load_config(&config_dir, self.profile.as_deref());
+                    let config = self.dev_config(config);
+                    // editors can save without changing anything, the included files are compared too
+                    if !force && self.config_ok && errors.is_empty() && config == self.config {
+                        log::debug!("config unchanged, skipping the reload");
+                        // the coalesced css reloads, or an scss save in the same event, still need the css,
+                        // it's only compiled again if the scss changed
+                        let response = self
+                            .load_css(&config_dir)
+                            .await
+                            .err()
+                            .map(|err| format!("Error:\n{err}"));
+                        for _ in 0..responses {
+                            let _ = server_response_send.send(response.clone());
+                        }
+                        continue;
+                    }
+                    // a reload gives the modules with disabled producers another chance
+                    self.producer_restarts.borrow_mut().clear();
+                    if force {
+                        self.css_hash = None;
+                    }
+                    log::info!("Reloading Config{}", if force { " (forced)" } else { "" });
+                    self.stats.borrow_mut().config_reloads += 1;
+
+                    let previous_config = self.config.clone();
+                    if self.config_ok {
+                        self.last_good_config = Some(previous_config.clone());
+                    }
+                    self.config = config;
+                    log::debug!("general_config: {:#?}", self.config.general_style_config);
 
-                    self.restart_producer_runtimes();
+                    let applied = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        self.apply_config_changes(&previous_config, force)
+                    }));
+                    match applied {
+                        Ok(()) => {
+                            if let Err(err) = self.load_css(&config_dir).await {
+                                errors.push(err.to_string());
+                            }
+                        }
+                        Err(_) => {
+                            errors.push("applying the config panicked".to_string());
+                            self.config_ok = false;
+                            if let Err(err) = self.revert_config(&config_dir).await {
+                                errors.push(err.to_string());
+                            }
+                        }
+                    }
+                    if errors.is_empty() {
+                        self.config_ok = true;
+                        self.last_reload.set(Some(Instant::now()));
+                    } else {
+                        self.config_ok = false;
+                    }
+                    // every coalesced reload that waits for a response gets the same one
+                    let response =
+                        (!errors.is_empty()).then(|| format!("Error:\n{}", errors.join("\n")));
+                    if self.config.general_style_config.dbus {
+                        let error = (!errors.is_empty()).then(|| errors.join("\n"));
+                        dbus::emit_reloaded(&self.application, error.as_deref());
+                    }
+                    self.run_reload_hook(response.as_deref());
+                    for _ in 0..responses {
+                        let _ = server_response_send.send(response.clone());
+                    }
+                }
+                BackendServerCommand::RevertConfig => {
+                    log::error!(
+                        "the producers crashed {CRASH_LOOP_RESTARTS} times in {CRASH_LOOP_WINDOW:?} after the reload"
+                    );
+                    self.config_ok = false;
+                    if let Err(err) = self.revert_config(&config_dir).await {
+                        log::error!("{err}");
+                    }
+                }
+                BackendServerCommand::ReloadCss => {
+                    // the reloads queued while the last one was compiling would all compile the same files
+                    let skipped = coalesce_css_reloads(&mut server_recv, &mut deferred);
+                    if skipped > 0 {
+                        log::debug!("coalesced {skipped} css reloads");
+                    }
+                    log::info!("Reloading Css");
+                    self.stats.borrow_mut().css_reloads += 1;
+                    let result = self.load_css(&config_dir).await;
+                    let error = result.err().map(|err| err.to_string());
+                    if self.config.general_style_config.dbus {
+                        dbus::emit_reloaded(&self.application, error.as_deref());
+                    }
+                    self.run_reload_hook(error.as_deref());
+                }
+                BackendServerCommand::ColorSchemeChanged(dark) => {
+                    if self.prefers_dark == Some(dark) {
+                        continue;
+                    }
+                    log::info!("color scheme changed, prefers dark: {dark}");
+                    self.prefers_dark = Some(dark);
+                    if self.config.general_style_config.follow_color_scheme {
+                        let _ = self.load_css(&config_dir).await;
+                    }
                 }
                 BackendServerCommand::Stop => {
                     log::info!("Quitting");
@@ -290,14 +894,181 @@ impl App {
                         let _ = server_response_send.send(None);
                     }
                 }
-                BackendServerCommand::ListActivities => match self.layout.clone() {
-                    Some(layout) => {
-                        let activities = layout.lock().await.1.list_activities();
-                        let mut response = String::new();
-                        for activity in activities {
-                            response += &activity.to_string();
-                            response += "\n";
+                BackendServerCommand::SetActivityVisible(id, visible) => {
+                    let response = match self.layout.clone() {
+                        Some(layout) => set_activity_visible(&layout, &id, visible).await,
+                        None => Some("no layout loaded".to_string()),
+                    };
+                    let _ = server_response_send.send(response);
+                }
+                BackendServerCommand::FilterActivities(pattern) => {
+                    let response = match pattern.as_deref().map(glob::Pattern::new).transpose() {
+                        Err(err) => Some(format!("Error:\ninvalid pattern: {err}")),
+                        Ok(pattern) => match self.layout.clone() {
+                            Some(layout) => {
+                                let layout = layout.lock().await;
+                                let activities: Vec<(ActivityIdentifier, Widget)> = layout
+                                    .1
+                                    .list_activities()
+                                    .into_iter()
+                                    .filter_map(|id| {
+                                        let widget = layout.1.get_activity(&id).into_option()?;
+                                        Some((id, widget.try_into().unwrap()))
+                                    })
+                                    .collect();
+                                let visibility: Vec<(ActivityIdentifier, bool)> = activities
+                                    .iter()
+                                    .map(|(id, widget)| (id.clone(), widget.is_visible()))
+                                    .collect();
+                                let (hidden, shown) = filter_changes(
+                                    &visibility,
+                                    pattern.as_ref(),
+                                    &self.filter_hidden,
+                                );
+                                // only visibility changes, so the layout reflows once on the next frame
+                                for (id, widget) in activities {
+                                    if hidden.contains(&id) {
+                                        widget.set_visible(false);
+                                    } else if shown.contains(&id) {
+                                        widget.set_visible(true);
+                                    }
+                                }
+                                self.filter_hidden = hidden;
+                                self.activity_filter =
+                                    pattern.map(|pattern| pattern.as_str().to_string());
+                                None
+                            }
+                            None => Some("no layout loaded".to_string()),
+                        },
+                    };
+                    let _ = server_response_send.send(response);
+                }
+                BackendServerCommand::SetActivityClasses(id, classes) => {
+                    let response = match self.layout.clone() {
+                        Some(layout) => {
+                            set_activity_classes(&layout, &mut self.activity_classes, id, classes)
+                                .await
+                        }
+                        None => Some("no layout loaded".to_string()),
+                    };
+                    let _ = server_response_send.send(response);
+                }
+                BackendServerCommand::GetGeneral(field) => {
+                    let response = match serde_json::to_value(&self.config.general_style_config) {
+                        Ok(serde_json::Value::Object(fields)) => match fields.get(&field) {
+                            Some(value) => value.to_string(),
+                            None => format!("Error:\nunknown field: {field}"),
+                        },
+                        Ok(_) => unreachable!(),
+                        Err(err) => format!("Error:\n{err}"),
+                    };
+                    let _ = server_response_send.send(Some(response));
+                }
+                BackendServerCommand::SetGeneral(field, value) => {
+                    let response = match set_general_field(
+                        &self.config.general_style_config,
+                        &field,
+                        &value,
+                    ) {
+                        Ok(config) => {
+                            log::info!("setting general_style_config.{field} to {value}");
+                            let previous =
+                                std::mem::replace(&mut self.config.general_style_config, config);
+                            self.update_general_configs();
+                            if previous.css_priority
+                                != self.config.general_style_config.css_priority
+                            {
+                                self.readd_css_provider();
+                            }
+                            self.update_x11_window_type();
+                            self.update_keep_above(previous.keep_above);
+                            if previous.icon_theme != self.config.general_style_config.icon_theme {
+                                apply_icon_theme(
+                                    self.config.general_style_config.icon_theme.as_deref(),
+                                );
+                            }
+                            self.load_generated_css();
+                            None
+                        }
+                        Err(err) => Some(format!("Error:\n{err}")),
+                    };
+                    let _ = server_response_send.send(response);
+                }
+                BackendServerCommand::SetLayoutProfile(profile) => {
+                    let layout_name = self.layout.clone().unwrap().lock().await.0.clone();
+                    let response = match profile {
+                        Some(profile)
+                            if !self
+                                .config
+                                .layout_configs
+                                .contains_key(&format!("{layout_name}.{profile}")) =>
+                        {
+                            Some(format!(
+                                "Error:\nno layout config named {layout_name}.{profile}"
+                            ))
                         }
+                        profile => {
+                            log::info!("using layout profile: {profile:?}");
+                            self.layout_profile = profile;
+                            self.load_layout_config();
+                            // for the layouts that set the configured layer again
+                            apply_keep_above(
+                                &self.application,
+                                self.config.general_style_config.keep_above,
+                            );
+                            None
+                        }
+                    };
+                    let _ = server_response_send.send(response);
+                }
+                BackendServerCommand::Stats => {
+                    let mut stats = self.stats.borrow().clone();
+                    stats.prefers_dark = self.prefers_dark;
+                    stats.layout_profile = self.layout_profile.clone();
+                    stats.activity_filter = self.activity_filter.clone();
+                    if let Some(layout) = self.layout.clone() {
+                        stats.registered_activities = layout.lock().await.1.list_activities().len();
+                    }
+                    let response = serde_json::to_string_pretty(&stats)
+                        .unwrap_or_else(|err| format!("Error:\n{err}"));
+                    let _ = server_response_send.send(Some(response));
+                }
+                BackendServerCommand::ListActivities(json) => match self.layout.clone() {
+                    Some(layout) => {
+                        let layout = layout.lock().await;
+                        let activities = layout.1.list_activities();
+                        let response = if json {
+                            let mut modules: BTreeMap<String, Vec<ActivityState>> = BTreeMap::new();
+                            for id in activities {
+                                let Some(widget) = layout.1.get_activity(&id).into_option() else {
+                                    continue;
+                                };
+                                let widget: Widget = widget.try_into().unwrap();
+                                let mode = widget.property::<ActivityMode>("mode");
+                                modules.entry(id.module()).or_default().push(ActivityState {
+                                    activity: id.activity(),
+                                    visible: widget.is_visible(),
+                                    mode: format!("{mode:?}"),
+                                    classes: widget
+                                        .css_classes()
+                                        .iter()
+                                        .map(|class| class.to_string())
+                                        .collect(),
+                                });
+                            }
+                            for states in modules.values_mut() {
+                                states.sort_by(|a, b| a.activity.cmp(&b.activity));
+                            }
+                            serde_json::to_string_pretty(&modules)
+                                .unwrap_or_else(|err| format!("Error:\n{err}"))
+                        } else {
+                            let mut response = String::new();
+                            for activity in activities {
+                                response += &activity.to_string();
+                                response += "\n";
+                            }
+                            response
+                        };
                         let _ = server_response_send.send(Some(response));
                     }
                     None => {
@@ -318,6 +1089,17 @@ impl App {
                         }
                     }
                 }
+                BackendServerCommand::DumpCss => {
+                    let response = if self.css_stale {
+                        format!(
+                            "/* stale: the last scss compile failed, this is the last css that compiled */\n{}",
+                            self.last_css
+                        )
+                    } else {
+                        self.last_css.clone()
+                    };
+                    let _ = server_response_send.send(Some(response));
+                }
                 BackendServerCommand::LayoutCliCommand(args) => {
                     let layout = self.layout.clone().unwrap();
                     let response = match layout.lock().await.1.cli_command(RString::from(args)) {
@@ -330,49 +1112,316 @@ impl App {
         }
     }
 
-    pub fn load_css(&mut self, config_dir: &Path) {
-        let css_content = grass::from_path(
-            config_dir.join("dynisland.scss"),
-            &grass::Options::default(),
-        );
+    /// Runs `post_reload_command` or, if there was an error, `on_reload_error_command` on another thread
+    fn run_reload_hook(&self, error: Option<&str>) {
+        let general_config = &self.config.general_style_config;
+        let command = match error {
+            None => general_config.post_reload_command.clone(),
+            Some(_) => general_config.on_reload_error_command.clone(),
+        };
+        let Some(command) = command else {
+            return;
+        };
+        let error = error.unwrap_or_default().to_string();
+        thread::spawn(move || {
+            match std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .env("DYNISLAND_RELOAD_ERROR", error)
+                .status()
+            {
+                Ok(status) if !status.success() => {
+                    log::warn!("reload hook `{command}` exited with {status}")
+                }
+                Ok(_) => {}
+                Err(err) => log::error!("failed to run reload hook `{command}`: {err}"),
+            }
+        });
+    }
+
+    /// Loads the fallback, generated and user's css. The scss is compiled on another thread
+    /// and awaited, so the ui keeps running, a compile that fails or times out keeps the previous css
+    pub async fn load_css(&mut self, config_dir: &Path) -> Result<()> {
+        self.load_fallback_css(config_dir);
+        self.load_generated_css();
+        if self.safe_mode {
+            return Ok(());
+        }
+        // grass can't emit source maps, the expanded style keeps the rules readable in the gtk inspector
+        let style = if self.config.general_style_config.css_compressed {
+            grass::OutputStyle::Compressed
+        } else {
+            grass::OutputStyle::Expanded
+        };
+        let dark = self
+            .config
+            .general_style_config
+            .follow_color_scheme
+            .then(|| self.prefers_dark.unwrap_or(false));
+        let prelude = scss_prelude(&self.config.general_style_config, dark);
+        let scss_hash = hash_scss_sources(config_dir, &prelude, style);
+        if !self.css_stale && self.css_hash == Some(scss_hash) {
+            log::debug!("scss unchanged, keeping the compiled css");
+            return Ok(());
+        }
+        let compile_start = Instant::now();
+        let scss_dir = config_dir.to_path_buf();
+        let css_content = compile_in_background(
+            move || compile_scss(&prelude, &scss_dir, style).map_err(|err| err.to_string()),
+            self.config.general_style_config.css_compile_timeout_ms,
+        )
+        .await;
+        self.stats.borrow_mut().last_css_compile_ms =
+            Some(compile_start.elapsed().as_secs_f64() * 1000.0);
         match css_content {
             Ok(content) => {
-                self.css_provider //TODO maybe save previous state before trying to update
-                    .load_from_string(&content);
+                self.swap_css_provider(&content);
+                self.last_css = content;
+                self.css_stale = false;
+                self.css_hash = Some(scss_hash);
+                Ok(())
             }
             Err(err) => {
-                log::warn!("failed to parse css: {}", err.to_string());
+                log::warn!("failed to parse css, keeping the previous one: {err}");
+                self.css_stale = true;
+                anyhow::bail!("failed to parse css: {err}");
+            }
+        }
+    }
+
+    /// Loads the first of `FALLBACK_FILES` found in the config dir, or the embedded `DEFAULT_SCSS`
+    fn load_fallback_css(&self, config_dir: &Path) {
+        let fallback_path = FALLBACK_FILES
+            .iter()
+            .map(|file| config_dir.join(file))
+            .find(|path| path.exists());
+        let css = match fallback_path {
+            Some(path) if !self.safe_mode => {
+                let css = if path.extension().is_some_and(|ext| ext == "css") {
+                    std::fs::read_to_string(&path).map_err(|err| err.to_string())
+                } else {
+                    grass::from_path(&path, &grass::Options::default())
+                        .map_err(|err| err.to_string())
+                };
+                match css {
+                    Ok(css) => {
+                        log::debug!("using {} as the fallback css", path.display());
+                        Some(css)
+                    }
+                    Err(err) => {
+                        log::warn!(
+                            "failed to load {}, using the default: {err}",
+                            path.display()
+                        );
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let css = css.unwrap_or_else(|| {
+            grass::from_string(DEFAULT_SCSS, &grass::Options::default()).unwrap()
+        });
+        self.fallback_provider.load_from_string(&css);
+    }
+
+    fn load_generated_css(&self) {
+        let config = &self.config.general_style_config;
+        let mut css = format!(
+            "window {{ transition: opacity 300ms ease-in-out; }}\n\
+             window.inactive {{ opacity: {}; }}\n",
+            config.inactive_opacity
+        );
+        // same selectors as default.scss, so the user's scss can still override them
+        let mut shape = String::new();
+        if let Some(radius) = config.corner_radius {
+            shape.push_str(&format!("border-radius: {radius}px; "));
+        }
+        if let Some(width) = config.border_width {
+            shape.push_str(&format!("border-width: {width}px; border-style: solid; "));
+        }
+        if let Some(color) = &config.border_color {
+            shape.push_str(&format!("border-color: {color}; "));
+        }
+        if !shape.is_empty() {
+            css.push_str(&format!("activity-widget {{ {shape}}}\n"));
+            if let Some(radius) = config.corner_radius {
+                css.push_str(&format!(
+                    "activity-widget .mode-minimal, activity-widget .mode-compact, \
+                     activity-widget .mode-expanded, activity-widget .mode-overlay \
+                     {{ border-radius: {radius}px; }}\n"
+                ));
             }
         }
+        self.generated_provider.load_from_string(&css);
+    }
+
+    /// Replaces the user's css provider with a new one containing `css`.
+    /// Reloading the same provider can leave the styles of the removed rules applied until a restart
+    fn swap_css_provider(&mut self, css: &str) {
+        let Some(display) = gdk::Display::default() else {
+            self.css_provider.load_from_string(css);
+            return;
+        };
+        let provider = CssProvider::new();
+        provider.load_from_string(css);
+        // added before removing the old one, so no frame is drawn without the user's css
+        gtk::style_context_add_provider_for_display(
+            &display,
+            &provider,
+            self.config.general_style_config.css_priority.map_gtk(),
+        );
+        gtk::style_context_remove_provider_for_display(&display, &self.css_provider);
+        self.css_provider = provider;
+    }
+
+    /// Adds the user's css provider again, with the priority from the current config
+    fn readd_css_provider(&self) {
+        let display = gdk::Display::default().unwrap();
+        gtk::style_context_remove_provider_for_display(&display, &self.css_provider);
+        gtk::style_context_add_provider_for_display(
+            &display,
+            &self.css_provider,
+            self.config.general_style_config.css_priority.map_gtk(),
+        );
+    }
+
+    /// Applies the sections of the config that are different from `previous_config`, or all of them if `force` is set
+    /// The css is loaded by the caller after this, with `load_css`
+    fn apply_config_changes(&mut self, previous_config: &Config, force: bool) {
+        // only do the work needed for the sections that changed
+        let module_config_changed =
+            force || previous_config.module_config != self.config.module_config;
+        if module_config_changed {
+            self.update_module_configs();
+        }
+        if force || previous_config.general_style_config != self.config.general_style_config {
+            self.update_general_configs();
+        }
+        if force
+            || previous_config.general_style_config.css_priority
+                != self.config.general_style_config.css_priority
+        {
+            self.readd_css_provider();
+        }
+        if force || previous_config.layout_configs != self.config.layout_configs {
+            self.load_layout_config();
+        }
+        if previous_config.loaded_modules != self.config.loaded_modules
+            || previous_config.layout != self.config.layout
+        {
+            log::warn!("loaded_modules and layout changes are applied after a restart");
+        }
+        self.update_x11_window_type();
+        self.update_keep_above(previous_config.general_style_config.keep_above);
+        if force
+            || previous_config.general_style_config.icon_theme
+                != self.config.general_style_config.icon_theme
+        {
+            apply_icon_theme(self.config.general_style_config.icon_theme.as_deref());
+        }
+        if self.config.general_style_config.warn_size_issues {
+            check_size_issues_when_idle(self.layout.clone().unwrap());
+        }
+
+        if module_config_changed {
+            self.restart_producer_runtimes();
+        }
+    }
+
+    fn update_x11_window_type(&self) {
+        let window_type = self.config.general_style_config.x11_window_type;
+        self.x11_window_type.set(window_type);
+        apply_x11_window_type(&self.application, window_type);
+    }
+
+    /// Applies `keep_above`, when it's turned off the layout config is loaded again to restore the layers
+    fn update_keep_above(&self, previous: bool) {
+        let keep_above = self.config.general_style_config.keep_above;
+        layout_manager::window_position::set_keep_above(keep_above);
+        self.keep_above.set(keep_above);
+        if keep_above {
+            apply_keep_above(&self.application, true);
+        } else if previous {
+            self.load_layout_config();
+        }
+    }
+
+    /// Goes back to the last config that reloaded without errors
+    async fn revert_config(&mut self, config_dir: &Path) -> Result<()> {
+        let Some(good_config) = self.last_good_config.clone() else {
+            anyhow::bail!("there is no known good config to revert to");
+        };
+        log::error!("!!! reverting to the last known good config, fix the config and reload !!!");
+        let bad_config = std::mem::replace(&mut self.config, good_config);
+        std::panic::catch_unwind(AssertUnwindSafe(|| {
+            self.apply_config_changes(&bad_config, false)
+        }))
+        .map_err(|_| anyhow::anyhow!("reverting to the last known good config panicked"))?;
+        self.load_css(config_dir).await
     }
 
     fn load_configs(&mut self, config_dir: &Path) {
-        self.config = config::get_config(config_dir);
+        if !self.safe_mode {
+            self.config = self.dev_config(config::get_config(config_dir, self.profile.as_deref()));
+        }
         log::debug!("general_config: {:#?}", self.config.general_style_config);
-        for (module_name, module) in self.module_map.blocking_lock().iter_mut() {
-            log::info!("loading config for module: {:#?}", module_name);
-            let config_to_parse = self.config.module_config.get(module_name);
+        self.update_module_configs();
+    }
+
+    /// Restricts `config` to the module run with `dynisland dev`, if any
+    fn dev_config(&self, config: Config) -> Config {
+        match &self.dev_module {
+            Some(module_name) => config.restricted_to_module(module_name),
+            None => config,
+        }
+    }
+
+    fn update_module_configs(&self) {
+        // like in `update_general_configs`, a task waiting for the lock keeps the updates in order
+        match self.module_map.try_lock() {
+            Ok(mut module_map) => {
+                Self::update_module_configs_on(&mut module_map, &self.config.module_config)
+            }
+            Err(_) => {
+                log::debug!("the modules are busy, updating their configs when they're released");
+                let module_map = self.module_map.clone();
+                let module_config = self.config.module_config.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    Self::update_module_configs_on(&mut *module_map.lock().await, &module_config);
+                });
+            }
+        }
+    }
+
+    fn update_module_configs_on(
+        module_map: &mut HashMap<String, ModuleType>,
+        module_config: &HashMap<String, Value>,
+    ) {
+        for (module_name, module) in module_map.iter_mut() {
+            log::info!(target: &module_log_target(module_name), "loading config for module: {:#?}", module_name);
+            let config_to_parse = module_config.get(module_name);
             let config_parsed = match config_to_parse {
                 Some(conf) => {
                     let confs: String = ron::ser::to_string_pretty(&conf, PrettyConfig::default())
                         .unwrap()
                         .into();
-                    log::trace!("{module_name} config before strip comments: {}", confs);
+                    log::trace!(target: &module_log_target(module_name), "{module_name} config before strip comments: {}", confs);
                     let mut confs = confs.replace("\\'", "\'");
                     if let Err(err) = json_strip_comments::strip(&mut confs) {
-                        log::warn!("failed to strip trailing commas from {module_name} err: {err}");
+                        log::warn!(target: &module_log_target(module_name), "failed to strip trailing commas from {module_name} err: {err}");
                     };
-                    log::trace!("{module_name} config: {}", confs);
+                    log::trace!(target: &module_log_target(module_name), "{module_name} config: {}", confs);
                     module.update_config(confs.into())
                 }
                 None => {
-                    log::debug!("no config for module: {:#?}", module_name);
+                    log::debug!(target: &module_log_target(module_name), "no config for module: {:#?}", module_name);
                     ROk(())
                 }
             };
             match config_parsed {
                 RErr(err) => {
-                    log::error!("failed to parse config for module {}: {err:?}", module_name)
+                    log::error!(target: &module_log_target(module_name), "failed to parse config for module {}: {err:?}", module_name)
                 }
                 ROk(()) => {
                     // log::debug!("{}: {:#?}", module_name, config_to_parse);
@@ -383,25 +1432,69 @@ impl App {
 
     //TODO let the modules handle this, something like module.update_general_config or module.update_config itself
     fn update_general_configs(&self) {
+        *self.general_config.borrow_mut() = self.config.general_style_config.clone();
+        self.inactive_delay.set(Duration::from_millis(
+            self.config.general_style_config.inactive_delay_ms,
+        ));
+        self.warn_size_issues
+            .set(self.config.general_style_config.warn_size_issues);
+        if !self.config.general_style_config.hide_until_first_activity
+            && self.waiting_first_activity.replace(false)
+        {
+            log::debug!("hide_until_first_activity was disabled, showing the windows");
+            show_windows(&self.application);
+        }
         let layout = self.layout.clone().unwrap();
-        let layout = layout.blocking_lock();
-        let activities = layout.1.list_activities();
-        for activity in activities {
-            let activity: Widget = layout
-                .1
-                .get_activity(&activity)
-                .unwrap()
-                .try_into()
-                .unwrap();
-            Self::update_general_configs_on_activity(&self.config.general_style_config, &activity);
+        // blocking on the main thread would deadlock against a task on it that holds the layout,
+        // a task waiting for the lock keeps the updates in order because the lock is fair
+        match layout.try_lock() {
+            Ok(layout) => {
+                Self::update_general_configs_on_layout(&layout.1, &self.config.general_style_config)
+            }
+            Err(_) => {
+                log::debug!("the layout is busy, updating the activities when it's released");
+                let config = self.config.general_style_config.clone();
+                let layout = layout.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    let layout = layout.lock().await;
+                    Self::update_general_configs_on_layout(&layout.1, &config);
+                });
+            }
+        }
+    }
+
+    fn update_general_configs_on_layout(layout: &LayoutManagerType, config: &GeneralConfig) {
+        for activity in layout.list_activities() {
+            let Some(activity) = layout.get_activity(&activity).into_option() else {
+                continue;
+            };
+            let activity: Widget = activity.try_into().unwrap();
+            Self::update_general_configs_on_activity(
+                config,
+                &activity,
+                config.animate_general_changes,
+            );
         }
     }
 
-    fn update_general_configs_on_activity(config: &GeneralConfig, activity: &Widget) {
+    /// `animate` eases the minimal height and the blur radius to the new values, it's not used for new activities
+    fn update_general_configs_on_activity(
+        config: &GeneralConfig,
+        activity: &Widget,
+        animate: bool,
+    ) {
         //TODO define property names as constants
-        activity.set_property("config-minimal-height", config.minimal_height as i32);
+        match activity.downcast_ref::<ActivityWidget>() {
+            Some(activity) if animate => {
+                activity.set_minimal_height(config.minimal_height as i32, true);
+                activity.set_blur_radius(config.blur_radius, true);
+            }
+            _ => {
+                activity.set_property("config-minimal-height", config.minimal_height as i32);
+                activity.set_property("config-blur-radius", config.blur_radius);
+            }
+        }
         activity.set_property("config-minimal-width", config.minimal_width as i32);
-        activity.set_property("config-blur-radius", config.blur_radius);
         activity.set_property("config-enable-drag-stretch", config.enable_drag_stretch);
         // activity.set_property("config-transition-duration", config.hide_widget_timeout_ms);
         // update widget size
@@ -418,9 +1511,42 @@ impl App {
 
     fn load_layout_config(&self) {
         let layout = self.layout.clone().unwrap();
-        let mut layout = layout.blocking_lock();
+        match layout.try_lock() {
+            Ok(mut layout) => Self::load_layout_config_on(
+                &mut layout,
+                &self.config.layout_configs,
+                self.layout_profile.as_deref(),
+            ),
+            Err(_) => {
+                log::debug!("the layout is busy, loading its config when it's released");
+                let layout = layout.clone();
+                let layout_configs = self.config.layout_configs.clone();
+                let layout_profile = self.layout_profile.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    Self::load_layout_config_on(
+                        &mut *layout.lock().await,
+                        &layout_configs,
+                        layout_profile.as_deref(),
+                    );
+                });
+            }
+        }
+    }
+
+    fn load_layout_config_on(
+        layout: &mut (String, LayoutManagerType),
+        layout_configs: &HashMap<String, Value>,
+        layout_profile: Option<&str>,
+    ) {
         let layout_name = layout.0.clone();
-        if let Some(config) = self.config.layout_configs.get(&layout_name) {
+        let profile_config = layout_profile.and_then(|profile| {
+            let config = layout_configs.get(&format!("{layout_name}.{profile}"));
+            if config.is_none() {
+                log::warn!("layout profile {profile} was removed, using the base config");
+            }
+            config
+        });
+        if let Some(config) = profile_config.or_else(|| layout_configs.get(&layout_name)) {
             let mut confs: String = ron::ser::to_string_pretty(&config, PrettyConfig::default())
                 .unwrap()
                 .into();
@@ -443,12 +1569,27 @@ impl App {
     }
 
     fn restart_producer_runtimes(&self) {
-        for module in self.module_map.blocking_lock().values_mut() {
-            module.restart_producers();
+        match self.module_map.try_lock() {
+            Ok(mut module_map) => Self::restart_producers_on(&mut module_map),
+            Err(_) => {
+                log::debug!(
+                    "the modules are busy, restarting their producers when they're released"
+                );
+                let module_map = self.module_map.clone();
+                glib::MainContext::default().spawn_local(async move {
+                    Self::restart_producers_on(&mut *module_map.lock().await);
+                });
+            }
         }
     }
 
-    pub fn get_default_config(self) -> (Config, String) {
+    fn restart_producers_on(module_map: &mut HashMap<String, ModuleType>) {
+        for module in module_map.values_mut() {
+            module.restart_producers();
+        }
+    }
+
+    pub fn get_default_config(self) -> (Config, String) {
         let mut base_conf = Config::default();
 
         // get all the loadable LayoutManager configs
@@ -470,6 +1611,12 @@ impl App {
                 .unwrap()
                 .default_config(),
         ));
+        layout_configs.push((
+            flow_layout::NAME.to_owned(),
+            flow_layout::new(self.application.clone().into())
+                .unwrap()
+                .default_config(),
+        ));
 
         base_conf.layout = Some(layout_configs.first().unwrap().0.clone());
 
@@ -484,7 +1631,10 @@ impl App {
                 RErr(e) => log::error!("error during creation of {mod_name}: {e:#?}"),
             };
         }
-        base_conf.loaded_modules = module_configs.iter().map(|v| v.0.to_owned()).collect();
+        base_conf.loaded_modules = module_configs
+            .iter()
+            .map(|v| ModuleEntry::Name(v.0.to_owned()))
+            .collect();
         let conf_str = "Config".to_owned() + &base_conf.to_string();
 
         // put the LayoutManager configs into base_conf and a string
@@ -532,6 +1682,7 @@ impl App {
         }
         mod_config_str += "    },";
         let conf_str = conf_str
+            .replacen("    loaded_modules: ", LOADED_MODULES_DOC, 1)
             .replace(
                 "layout_configs: {},",
                 &("layout_configs: ".to_owned() + &lm_config_str),
@@ -541,7 +1692,7 @@ impl App {
                 &("module_config: ".to_owned() + &mod_config_str),
             );
         // check that the generated config is valid
-        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let options = config::ron_options();
         if options.from_str::<Config>(&conf_str).is_ok() {
             (base_conf, conf_str)
         } else {
@@ -567,25 +1718,574 @@ impl Default for App {
             app_send: None,
             config: config::Config::default(),
             css_provider: gtk::CssProvider::new(),
+            fallback_provider: gtk::CssProvider::new(),
+            generated_provider: gtk::CssProvider::new(),
+            inactive_delay: Rc::new(Cell::new(Duration::ZERO)),
+            keep_above: Rc::new(Cell::new(false)),
+            general_config: Rc::new(RefCell::new(GeneralConfig::default())),
+            waiting_first_activity: Rc::new(Cell::new(false)),
+            x11_window_type: Rc::new(Cell::new(X11WindowType::default())),
+            warn_size_issues: Rc::new(Cell::new(false)),
+            prefers_dark: None,
+            last_css: String::new(),
+            css_stale: false,
             config_dir: config::get_default_config_path(),
+            open_inspector: false,
+            safe_mode: false,
+            profile: None,
+            layout_profile: None,
+            once: None,
+            stats: Rc::new(RefCell::new(Stats::default())),
+            activity_classes: HashMap::new(),
+            last_good_config: None,
+            config_ok: true,
+            last_reload: Rc::new(Cell::new(None)),
+            producer_restarts: Rc::new(RefCell::new(HashMap::new())),
+            css_hash: None,
+            dev_module: None,
+            activity_filter: None,
+            filter_hidden: HashSet::new(),
         }
     }
 }
 
+/// Returns a copy of `config` with `field` set to `value`, parsed as json.
+/// Values that aren't valid json are used as strings
+fn set_general_field(config: &GeneralConfig, field: &str, value: &str) -> Result<GeneralConfig> {
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(config)? else {
+        unreachable!()
+    };
+    if !fields.contains_key(field) {
+        anyhow::bail!("unknown field: {field}");
+    }
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    fields.insert(field.to_string(), value);
+    let mut config: GeneralConfig = serde_json::from_value(serde_json::Value::Object(fields))
+        .with_context(|| format!("invalid value for {field}"))?;
+    config.validate();
+    Ok(config)
+}
+
+/// Executes a command sent by a module.
+///
+/// This is what the UI command consumer runs for every message,
+/// it doesn't depend on the gtk application so it can be driven without one.
+pub async fn handle_ui_command(
+    command: UIServerCommand,
+    module_map: &Mutex<HashMap<String, ModuleType>>,
+    layout: &Rc<Mutex<(String, LayoutManagerType)>>,
+    config: &GeneralConfig,
+    pending_removals: &PendingRemovals,
+) {
+    match command {
+        UIServerCommand::AddActivity {
+            activity_id,
+            widget,
+        } => {
+            let activity: Widget = match widget.try_into() {
+                Ok(act) => act,
+                Err(err) => {
+                    log::error!("error while converting SabiWidget to Widget, maybe it was deallocated after UIServerCommand::AddActivity was sent: {err:#?}");
+                    return;
+                }
+            };
+
+            // not animated, the activity is just being shown
+            App::update_general_configs_on_activity(config, &activity, false);
+
+            let removal_cancelled = match pending_removals.borrow_mut().remove(&activity_id) {
+                Some(source) => {
+                    // the entry is removed when the timeout fires, so the source is still pending
+                    source.remove();
+                    log::debug!("cancelled pending removal of {activity_id}");
+                    true
+                }
+                None => false,
+            };
+
+            let mut layout = layout.lock().await;
+            if let Some(registered) = layout.1.get_activity(&activity_id).into_option() {
+                if !removal_cancelled {
+                    log::debug!(target: &module_log_target(activity_id.module()), "activity already registered on {}", activity_id.module());
+                    return;
+                }
+                let registered: Option<Widget> = registered.try_into().ok();
+                if registered.as_ref() == Some(&activity) {
+                    return;
+                }
+                // the module created a new widget, swap it with the old one
+                layout.1.remove_activity(&activity_id);
+            }
+
+            layout.1.add_activity(&activity_id, activity.into());
+            log::info!(target: &module_log_target(activity_id.module()), "registered activity on {}", activity_id.module());
+        }
+        UIServerCommand::RemoveActivity { activity_id } => {
+            if config.remove_debounce_ms == 0 {
+                remove_activity(layout, &activity_id).await;
+                return;
+            }
+            let mut pending = pending_removals.borrow_mut();
+            if pending.contains_key(&activity_id) {
+                return;
+            }
+            let layout = layout.clone();
+            let pending_removals = pending_removals.clone();
+            let id = activity_id.clone();
+            let source = glib::timeout_add_local_once(
+                Duration::from_millis(config.remove_debounce_ms),
+                move || {
+                    pending_removals.borrow_mut().remove(&id);
+                    glib::MainContext::default().spawn_local(async move {
+                        remove_activity(&layout, &id).await;
+                    });
+                },
+            );
+            pending.insert(activity_id, source);
+        }
+        UIServerCommand::RestartProducers { module_name } => {
+            if let Some(module) = module_map.lock().await.get(module_name.as_str()) {
+                module.restart_producers();
+            }
+        }
+        UIServerCommand::RequestNotification {
+            activity_id,
+            mode,
+            duration,
+        } => {
+            if mode > 3 {
+                return;
+            }
+            let layout = layout.lock().await;
+            if layout.1.get_activity(&activity_id).is_none() {
+                return;
+            }
+            layout.1.activity_notification(&activity_id, mode, duration);
+        }
+    }
+}
+
+async fn remove_activity(
+    layout: &Mutex<(String, LayoutManagerType)>,
+    activity_id: &ActivityIdentifier,
+) {
+    let mut layout = layout.lock().await;
+    if layout.1.get_activity(activity_id).is_some() {
+        layout.1.remove_activity(activity_id);
+        log::info!(target: &module_log_target(activity_id.module()), "unregistered activity on {}", activity_id.module());
+    } else {
+        log::warn!("error removing activity, not found: {:?}", activity_id);
+    }
+}
+
+/// Quits after the next frame of the first window is drawn, saving it to `screenshot` if set
+fn render_once(
+    application: &gtk::Application,
+    screenshot: Option<PathBuf>,
+    failed: Rc<Cell<bool>>,
+) {
+    let Some(window) = application.windows().first().cloned() else {
+        log::error!("there are no windows to render");
+        failed.set(true);
+        application.quit();
+        return;
+    };
+    let Some(frame_clock) = window.frame_clock() else {
+        log::error!("the window has no frame clock");
+        failed.set(true);
+        application.quit();
+        return;
+    };
+    let application = application.clone();
+    let done = Cell::new(false);
+    let render_window = window.clone();
+    frame_clock.connect_after_paint(move |_| {
+        if done.replace(true) {
+            return;
+        }
+        if let Some(path) = &screenshot {
+            match save_screenshot(&render_window, path) {
+                Ok(()) => log::info!("saved screenshot to {}", path.display()),
+                Err(err) => {
+                    log::error!("failed to save screenshot: {err:#}");
+                    failed.set(true);
+                }
+            }
+        }
+        application.quit();
+    });
+    window.queue_draw();
+}
+
+/// Resolves after the next frame of the window is painted
+async fn next_frame(window: &gtk::Window) {
+    let Some(frame_clock) = window.frame_clock() else {
+        return;
+    };
+    let (send, recv) = tokio::sync::oneshot::channel::<()>();
+    let send = RefCell::new(Some(send));
+    let handler = frame_clock.connect_after_paint(move |_| {
+        if let Some(send) = send.borrow_mut().take() {
+            let _ = send.send(());
+        }
+    });
+    window.queue_draw();
+    let _ = recv.await;
+    frame_clock.disconnect(handler);
+}
+
+fn save_screenshot(window: &gtk::Window, path: &Path) -> Result<()> {
+    let paintable = gtk::WidgetPaintable::new(Some(window));
+    let snapshot = gtk::Snapshot::new();
+    paintable.snapshot(&snapshot, window.width() as f64, window.height() as f64);
+    let node = snapshot.to_node().context("nothing was rendered")?;
+    let renderer = window.renderer().context("the window has no renderer")?;
+    renderer
+        .render_texture(node, None)
+        .save_to_png(path)
+        .context("failed to write the png")?;
+    Ok(())
+}
+
+/// Adds the `inactive` class to the window when the pointer has been away from it for `delay`,
+/// gtk skips the opacity transition if animations are disabled
+fn setup_inactive_class(window: &gtk::Window, delay: Rc<Cell<Duration>>) {
+    let pending: Rc<RefCell<Option<SourceId>>> = Rc::new(RefCell::new(None));
+    let motion = gtk::EventControllerMotion::new();
+    motion.set_name(Some("inactive_controller"));
+    let enter_pending = pending.clone();
+    motion.connect_enter(move |controller, _, _| {
+        if let Some(source) = enter_pending.borrow_mut().take() {
+            source.remove();
+        }
+        controller.widget().remove_css_class("inactive");
+    });
+    let leave_delay = delay.clone();
+    let leave_pending = pending.clone();
+    motion.connect_leave(move |controller| {
+        schedule_inactive(&controller.widget(), leave_delay.get(), &leave_pending);
+    });
+    window.add_controller(motion);
+    schedule_inactive(window.upcast_ref(), delay.get(), &pending);
+}
+
+fn schedule_inactive(widget: &Widget, delay: Duration, pending: &Rc<RefCell<Option<SourceId>>>) {
+    if let Some(source) = pending.borrow_mut().take() {
+        source.remove();
+    }
+    let widget = widget.clone();
+    let fired = pending.clone();
+    let source = glib::timeout_add_local_once(delay, move || {
+        // the source is already gone, it only has to be forgotten
+        fired.borrow_mut().take();
+        widget.add_css_class("inactive");
+    });
+    *pending.borrow_mut() = Some(source);
+}
+
+/// Warns about the activities that are smaller than their minimum size, after the next layout
+fn check_size_issues_when_idle(layout: Rc<Mutex<(String, LayoutManagerType)>>) {
+    glib::idle_add_local_once(move || {
+        let Ok(layout) = layout.try_lock() else {
+            log::debug!("layout is busy, skipping the size check");
+            return;
+        };
+        for id in layout.1.list_activities() {
+            let Some(widget) = layout.1.get_activity(&id).into_option() else {
+                continue;
+            };
+            let Ok(widget) = Widget::try_from(widget) else {
+                continue;
+            };
+            if !widget.is_mapped() {
+                continue;
+            }
+            let (min_width, ..) = widget.measure(gtk::Orientation::Horizontal, -1);
+            let (min_height, ..) = widget.measure(gtk::Orientation::Vertical, widget.width());
+            if widget.width() < min_width || widget.height() < min_height {
+                log::warn!(
+                    "activity {id} is allocated {}x{} but needs at least {min_width}x{min_height}, it will be clipped",
+                    widget.width(),
+                    widget.height()
+                );
+            }
+        }
+    });
+}
+
+fn show_windows(application: &gtk::Application) {
+    for window in application.windows() {
+        window.present();
+    }
+}
+
+/// Runs `f` on the main context once there is a display (if `wait_for_display`) and `delay` has passed
+fn run_when_ready(wait_for_display: bool, delay: Duration, f: impl FnOnce() + 'static) {
+    if wait_for_display && gdk::Display::default().is_none() {
+        log::info!("waiting for the display to be available");
+        let mut f = Some(f);
+        glib::timeout_add_local(Duration::from_millis(100), move || {
+            if gdk::Display::default().is_none() {
+                return glib::ControlFlow::Continue;
+            }
+            if let Some(f) = f.take() {
+                run_when_ready(false, delay, f);
+            }
+            glib::ControlFlow::Break
+        });
+        return;
+    }
+    if delay.is_zero() {
+        f();
+    } else {
+        log::info!("delaying startup by {}ms", delay.as_millis());
+        glib::timeout_add_local_once(delay, f);
+    }
+}
+
+/// Variables defined before importing `dynisland.scss`, the invalid `scss_variables` are skipped.
+/// `prefers_dark` is `None` when `follow_color_scheme` is off
+fn scss_prelude(config: &GeneralConfig, prefers_dark: Option<bool>) -> String {
+    let mut prelude = String::new();
+    if let Some(dark) = prefers_dark {
+        prelude += &format!("$prefers-dark: {dark};\n");
+    }
+    prelude += &format!(
+        "$minimal-height: {}px;\n$minimal-width: {}px;\n$blur-radius: {}px;\n",
+        config.minimal_height, config.minimal_width, config.blur_radius
+    );
+    // sorted, so the same variables always give the same css hash
+    let mut variables: Vec<_> = config.scss_variables.iter().collect();
+    variables.sort();
+    for (name, value) in variables {
+        let name = name.trim_start_matches('$');
+        let valid_name = !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        // the value is an scss expression, but it can't end the declaration and start a rule
+        if !valid_name || value.trim().is_empty() || value.contains([';', '{', '}', '\n']) {
+            log::warn!("skipping invalid scss variable ${name}: {value}");
+            continue;
+        }
+        prelude += &format!("${name}: {};\n", value.trim());
+    }
+    prelude
+}
+
+/// Runs `compile` on another thread, so an import loop or a huge stylesheet can't block the ui,
+/// giving up after `timeout_ms` (0 waits forever)
+async fn compile_in_background(
+    compile: impl FnOnce() -> Result<String, String> + Send + 'static,
+    timeout_ms: u64,
+) -> Result<String, String> {
+    let (css_send, css_recv) = tokio::sync::oneshot::channel();
+    thread::Builder::new()
+        .name("scss-compile".to_string())
+        .spawn(move || {
+            // the receiver is gone if the compile timed out
+            let _ = css_send.send(compile());
+        })
+        .map_err(|err| format!("failed to spawn the scss compile thread: {err}"))?;
+    let compiled = async {
+        css_recv
+            .await
+            .unwrap_or_else(|_| Err("the compile thread panicked".to_string()))
+    };
+    match timeout_ms {
+        0 => compiled.await,
+        timeout_ms => glib::future_with_timeout(Duration::from_millis(timeout_ms), compiled)
+            .await
+            .unwrap_or_else(|_| Err(format!("compile timed out after {timeout_ms}ms"))),
+    }
+}
+
+/// Compiles `dynisland.scss` from `scss_dir` after the prelude
+fn compile_scss(
+    prelude: &str,
+    scss_dir: &Path,
+    style: grass::OutputStyle,
+) -> Result<String, Box<grass::Error>> {
+    let options = grass::Options::default().style(style);
+    // variables defined before an @import are visible in the imported file
+    grass::from_string(
+        format!("{prelude}@import \"dynisland\";\n"),
+        &options.load_path(scss_dir),
+    )
+}
+
+/// Hashes the content of the style sheets in the config dir and its subdirectories,
+/// with the prelude and the options that change the compiled css, so a save that doesn't change them doesn't recompile.
+fn hash_scss_sources(config_dir: &Path, prelude: &str, style: grass::OutputStyle) -> u64 {
+    fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // symlinked dirs aren't followed, so a link loop can't recurse forever
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if is_dir && !hidden {
+                collect(&path, files);
+            } else if path
+                .extension()
+                .is_some_and(|ext| ext == "scss" || ext == "sass" || ext == "css")
+            {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    collect(config_dir, &mut files);
+    files.sort();
+    let mut hasher = DefaultHasher::new();
+    prelude.hash(&mut hasher);
+    matches!(style, grass::OutputStyle::Expanded).hash(&mut hasher);
+    for file in files {
+        file.hash(&mut hasher);
+        std::fs::read(&file).unwrap_or_default().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Removes the queued `ReloadCss` commands, moving the other commands to `deferred` in order
+fn coalesce_css_reloads(
+    server_recv: &mut tokio::sync::mpsc::UnboundedReceiver<BackendServerCommand>,
+    deferred: &mut VecDeque<BackendServerCommand>,
+) -> usize {
+    let mut skipped = 0;
+    while let Ok(command) = server_recv.try_recv() {
+        match command {
+            BackendServerCommand::ReloadCss => skipped += 1,
+            command => deferred.push_back(command),
+        }
+    }
+    skipped
+}
+
+/// Removes the queued `ReloadConfig` commands and the `ReloadCss` ones, that the config reload already covers,
+/// moving the other commands to `deferred` in order.
+///
+/// Returns the number of removed commands, how many of them wait for a response and whether any was forced
+fn coalesce_config_reloads(
+    server_recv: &mut tokio::sync::mpsc::UnboundedReceiver<BackendServerCommand>,
+    deferred: &mut VecDeque<BackendServerCommand>,
+) -> (usize, usize, bool) {
+    let mut skipped = 0;
+    let mut responses = 0;
+    let mut forced = false;
+    while let Ok(command) = server_recv.try_recv() {
+        match command {
+            BackendServerCommand::ReloadConfig { respond, force } => {
+                skipped += 1;
+                responses += usize::from(respond);
+                forced |= force;
+            }
+            BackendServerCommand::ReloadCss => skipped += 1,
+            command => deferred.push_back(command),
+        }
+    }
+    (skipped, responses, forced)
+}
+
+/// Applies the taskbar and pager hints on every window, does nothing if not running on X11
+fn apply_x11_window_type(application: &gtk::Application, window_type: X11WindowType) {
+    let is_x11 = gdk::Display::default()
+        .map(|display| display.is::<gdk_x11::X11Display>())
+        .unwrap_or(false);
+    if !is_x11 {
+        return;
+    }
+    for window in application.windows() {
+        set_x11_hints(&window, window_type);
+    }
+    log::debug!("applied X11 window type: {window_type:?}");
+}
+
+/// Applies the X11 window type to the windows created later, like the ones `close_if_empty` recreates.
+/// The X11 surface exists once the window is realized
+fn setup_x11_window_type(window: &gtk::Window, window_type: Rc<Cell<X11WindowType>>) {
+    window.connect_realize(move |window| set_x11_hints(window, window_type.get()));
+}
+
+fn set_x11_hints(window: &gtk::Window, window_type: X11WindowType) {
+    let Some(surface) = window.surface() else {
+        return;
+    };
+    if let Ok(surface) = surface.downcast::<gdk_x11::X11Surface>() {
+        let skip_hints = !matches!(window_type, X11WindowType::Normal);
+        surface.set_skip_taskbar_hint(skip_hints);
+        surface.set_skip_pager_hint(skip_hints);
+    }
+}
+
+/// Puts a window created by the layout, also after startup, on the `Overlay` layer when `keep_above` is set.
+/// The layout initializes layer-shell before the window is realized, so it's applied then
+fn setup_keep_above(window: &gtk::Window, keep_above: Rc<Cell<bool>>) {
+    window.connect_realize(move |window| {
+        if keep_above.get() && window.is_layer_window() {
+            window.set_layer(gtk_layer_shell::Layer::Overlay);
+        }
+    });
+}
+
+fn apply_keep_above(application: &gtk::Application, keep_above: bool) {
+    if !keep_above {
+        return;
+    }
+    for window in application.windows() {
+        if window.is_layer_window() {
+            window.set_layer(gtk_layer_shell::Layer::Overlay);
+        } else {
+            log::debug!("keep_above only works on layer-shell windows");
+        }
+    }
+}
+
+/// Sets the theme of the display's icon theme, which is shared with the modules. `None` goes back to the gtk settings
+fn apply_icon_theme(theme: Option<&str>) {
+    let Some(display) = gdk::Display::default() else {
+        return;
+    };
+    // gtk drops its cached icons when the theme name changes
+    gtk::IconTheme::for_display(&display).set_theme_name(theme);
+    log::debug!("icon theme: {theme:?}");
+}
+
 fn start_config_dir_watcher(
     server_send: tokio::sync::mpsc::UnboundedSender<BackendServerCommand>,
     config_dir: &Path,
+    ignore_patterns: &[String],
 ) -> RecommendedWatcher {
     log::info!("starting config watcher");
+    let ignore_patterns: Vec<glob::Pattern> = ignore_patterns
+        .iter()
+        .filter_map(|pattern| match glob::Pattern::new(pattern) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                log::warn!("invalid ignore pattern {pattern}: {err}");
+                None
+            }
+        })
+        .collect();
+    let watched_dir = config_dir.to_path_buf();
     let mut watcher =
         notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
             Ok(evt) => {
                 // log::info!("config event: {:?}",evt.kind);
                 match evt.kind {
                     notify::EventKind::Modify(notify::event::ModifyKind::Data(_)) => {
-                        log::debug!("Config change detected");
+                        let Some(command) =
+                            config_change_command(&evt.paths, &watched_dir, &ignore_patterns)
+                        else {
+                            log::trace!("ignoring config dir event for {:?}", evt.paths);
+                            return;
+                        };
                         server_send
-                            .send(BackendServerCommand::ReloadConfig)
+                            .send(command)
                             .expect("Failed to send notification")
                     }
                     notify::EventKind::Create(_) => {
@@ -606,10 +2306,50 @@ fn start_config_dir_watcher(
     watcher
 }
 
+/// The reload needed after a change to `paths`, `None` if they are all ignored
+fn config_change_command(
+    paths: &[PathBuf],
+    config_dir: &Path,
+    patterns: &[glob::Pattern],
+) -> Option<BackendServerCommand> {
+    let paths: Vec<&PathBuf> = paths
+        .iter()
+        .filter(|path| !is_ignored(path, config_dir, patterns))
+        .collect();
+    if paths.is_empty() {
+        return None;
+    }
+    // only the css needs to be reloaded if the scss files are the only ones that changed
+    if paths
+        .iter()
+        .all(|path| path.extension().is_some_and(|ext| ext == "scss"))
+    {
+        log::debug!("Css change detected");
+        Some(BackendServerCommand::ReloadCss)
+    } else {
+        log::debug!("Config change detected");
+        Some(BackendServerCommand::ReloadConfig {
+            respond: false,
+            force: false,
+        })
+    }
+}
+
+/// Checks every component of the path, relative to the config dir, against the ignore patterns
+fn is_ignored(path: &Path, config_dir: &Path, patterns: &[glob::Pattern]) -> bool {
+    let relative_path = path.strip_prefix(config_dir).unwrap_or(path);
+    relative_path.iter().any(|component| {
+        component
+            .to_str()
+            .is_some_and(|component| patterns.iter().any(|pattern| pattern.matches(component)))
+    })
+}
+
 fn start_ipc_server(
     runtime_path: std::path::PathBuf,
     server_send: tokio::sync::mpsc::UnboundedSender<BackendServerCommand>,
     mut server_response_recv: tokio::sync::mpsc::UnboundedReceiver<Option<String>>,
+    reload_timeout: Duration,
 ) {
     let thread = thread::Builder::new().name("ipc-server".to_string());
     thread
@@ -629,6 +2369,7 @@ fn start_ipc_server(
                         &runtime_path,
                         server_send.clone(),
                         &mut server_response_recv,
+                        reload_timeout,
                     )
                     .await
                     {
@@ -649,3 +2390,699 @@ fn start_ipc_server(
         })
         .expect("failed to spawn file-watcher thread");
 }
+
+#[cfg(test)]
+mod tests {
+    use abi_stable::{sabi_trait::TD_CanDowncast, std_types::RVec};
+    use dynisland_core::abi::{
+        layout::{SabiLayoutManager, SabiLayoutManager_TO},
+        SabiWidget,
+    };
+
+    use super::*;
+    use crate::test_util::with_gtk;
+
+    /// Keeps the activities it's given, like a layout without windows
+    #[derive(Default)]
+    struct MockLayout {
+        activities: HashMap<ActivityIdentifier, Widget>,
+        /// add and remove calls, each one reflows a real layout
+        reflows: Rc<Cell<usize>>,
+    }
+
+    impl SabiLayoutManager for MockLayout {
+        fn init(&mut self) {}
+        fn update_config(&mut self, _config: RString) -> RResult<(), RBoxError> {
+            ROk(())
+        }
+        fn default_config(&self) -> RResult<RString, RBoxError> {
+            ROk(RString::new())
+        }
+        fn add_activity(&mut self, activity_id: &ActivityIdentifier, widget: SabiWidget) {
+            let widget: Widget = widget.try_into().unwrap();
+            self.activities.insert(activity_id.clone(), widget);
+            self.reflows.set(self.reflows.get() + 1);
+        }
+        fn get_activity(&self, activity: &ActivityIdentifier) -> ROption<SabiWidget> {
+            self.activities
+                .get(activity)
+                .map(|widget| SabiWidget::from(widget.clone()))
+                .into()
+        }
+        fn remove_activity(&mut self, activity: &ActivityIdentifier) {
+            self.activities.remove(activity);
+            self.reflows.set(self.reflows.get() + 1);
+        }
+        fn list_activities(&self) -> RVec<ActivityIdentifier> {
+            self.activities.keys().cloned().collect()
+        }
+        fn list_windows(&self) -> RVec<RString> {
+            RVec::new()
+        }
+        fn activity_notification(
+            &self,
+            _activity: &ActivityIdentifier,
+            _mode_id: u8,
+            _duration: ROption<u64>,
+        ) {
+        }
+        fn cli_command(&self, _args: RString) -> RResult<RString, RBoxError> {
+            ROk(RString::new())
+        }
+    }
+
+    fn mock_layout() -> Rc<Mutex<(String, LayoutManagerType)>> {
+        counting_mock_layout().0
+    }
+
+    /// Also returns the count of the add and remove calls
+    fn counting_mock_layout() -> (Rc<Mutex<(String, LayoutManagerType)>>, Rc<Cell<usize>>) {
+        let mock = MockLayout::default();
+        let reflows = mock.reflows.clone();
+        let layout = SabiLayoutManager_TO::from_value(mock, TD_CanDowncast);
+        (
+            Rc::new(Mutex::new(("MockLayout".to_string(), layout))),
+            reflows,
+        )
+    }
+
+    fn new_activity() -> Widget {
+        glib::Object::new::<ActivityWidget>().upcast()
+    }
+
+    fn add_activity(
+        layout: &Rc<Mutex<(String, LayoutManagerType)>>,
+        id: &ActivityIdentifier,
+        widget: &Widget,
+    ) {
+        let command = UIServerCommand::AddActivity {
+            activity_id: id.clone(),
+            widget: widget.clone().into(),
+        };
+        glib::MainContext::default().block_on(handle_ui_command(
+            command,
+            &Mutex::new(HashMap::new()),
+            layout,
+            &GeneralConfig::default(),
+            &Rc::new(RefCell::new(HashMap::new())),
+        ));
+    }
+
+    #[test]
+    fn add_activity_registers_it_in_the_layout() {
+        with_gtk(|| {
+            let layout = mock_layout();
+            let id = ActivityIdentifier::new("test-module", "test-activity");
+            let widget = new_activity();
+            add_activity(&layout, &id, &widget);
+
+            let layout = layout.try_lock().unwrap();
+            assert_eq!(layout.1.list_activities().len(), 1);
+            let registered: Widget = layout
+                .1
+                .get_activity(&id)
+                .into_option()
+                .expect("the activity should be registered")
+                .try_into()
+                .unwrap();
+            assert_eq!(registered, widget);
+        });
+    }
+
+    #[test]
+    fn hidden_activities_stay_in_the_layout() {
+        with_gtk(|| {
+            let layout = mock_layout();
+            let id = ActivityIdentifier::new("test-module", "test-activity");
+            let widget = new_activity();
+            add_activity(&layout, &id, &widget);
+            let context = glib::MainContext::default();
+
+            assert_eq!(
+                context.block_on(set_activity_visible(&layout, &id, false)),
+                None
+            );
+            assert!(!widget.is_visible());
+            assert_eq!(layout.try_lock().unwrap().1.list_activities().len(), 1);
+
+            assert_eq!(
+                context.block_on(set_activity_visible(&layout, &id, true)),
+                None
+            );
+            assert!(widget.is_visible());
+            let registered: Widget = layout
+                .try_lock()
+                .unwrap()
+                .1
+                .get_activity(&id)
+                .into_option()
+                .expect("the activity should still be registered")
+                .try_into()
+                .unwrap();
+            assert_eq!(registered, widget);
+
+            let missing = ActivityIdentifier::new("test-module", "missing");
+            assert_eq!(
+                context.block_on(set_activity_visible(&layout, &missing, false)),
+                Some("activity not found: missing@test-module".to_string())
+            );
+        });
+    }
+
+    #[test]
+    fn activity_classes_replace_the_previous_ones() {
+        with_gtk(|| {
+            let layout = mock_layout();
+            let id = ActivityIdentifier::new("test-module", "test-activity");
+            let widget = new_activity();
+            add_activity(&layout, &id, &widget);
+            widget.add_css_class("from-the-module");
+            let mut activity_classes = HashMap::new();
+            let mut set_classes = |classes: &[&str]| {
+                let classes = classes.iter().map(|class| class.to_string()).collect();
+                glib::MainContext::default().block_on(set_activity_classes(
+                    &layout,
+                    &mut activity_classes,
+                    id.clone(),
+                    classes,
+                ))
+            };
+
+            assert_eq!(set_classes(&["urgent", "red"]), None);
+            assert!(widget.has_css_class("urgent") && widget.has_css_class("red"));
+
+            assert_eq!(set_classes(&["red", "blue"]), None);
+            assert!(!widget.has_css_class("urgent"));
+            assert!(widget.has_css_class("red") && widget.has_css_class("blue"));
+
+            assert_eq!(set_classes(&[]), None);
+            assert!(!widget.has_css_class("red") && !widget.has_css_class("blue"));
+            // the classes that weren't set by the command are kept
+            assert!(widget.has_css_class("from-the-module"));
+        });
+    }
+
+    #[test]
+    fn update_general_configs_waits_for_a_busy_layout() {
+        with_gtk(|| {
+            let layout = mock_layout();
+            let widget = new_activity();
+            add_activity(
+                &layout,
+                &ActivityIdentifier::new("test-module", "test-activity"),
+                &widget,
+            );
+            let mut app = App {
+                layout: Some(layout.clone()),
+                ..Default::default()
+            };
+            app.config.general_style_config.minimal_width = 123;
+
+            let guard = layout.try_lock().unwrap();
+            // this would deadlock if it blocked on the lock
+            app.update_general_configs();
+            assert_ne!(widget.property::<i32>("config-minimal-width"), 123);
+
+            drop(guard);
+            let context = glib::MainContext::default();
+            while context.iteration(false) {}
+            assert_eq!(widget.property::<i32>("config-minimal-width"), 123);
+        });
+    }
+
+    fn default_ignore_patterns() -> Vec<glob::Pattern> {
+        crate::config::DEFAULT_IGNORE_PATTERNS
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn editor_files_are_ignored() {
+        let config_dir = Path::new("/home/user/.config/dynisland");
+        let patterns = default_ignore_patterns();
+        assert!(is_ignored(
+            &config_dir.join(".dynisland.scss.swp"),
+            config_dir,
+            &patterns
+        ));
+        assert!(is_ignored(
+            &config_dir.join(".git/index"),
+            config_dir,
+            &patterns
+        ));
+        assert!(!is_ignored(
+            &config_dir.join("dynisland.scss"),
+            config_dir,
+            &patterns
+        ));
+        // only the part inside the config dir is matched
+        assert!(!is_ignored(
+            Path::new("/tmp/dir.tmp/dynisland/dynisland.ron"),
+            Path::new("/tmp/dir.tmp/dynisland"),
+            &patterns
+        ));
+        assert!(config_change_command(
+            &[config_dir.join(".dynisland.scss.swp")],
+            config_dir,
+            &patterns
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn scss_changes_only_reload_the_css() {
+        let config_dir = Path::new("/home/user/.config/dynisland");
+        let patterns = default_ignore_patterns();
+        let scss = config_dir.join("dynisland.scss");
+        let ron = config_dir.join("dynisland.ron");
+        assert!(matches!(
+            config_change_command(&[scss.clone()], config_dir, &patterns),
+            Some(BackendServerCommand::ReloadCss)
+        ));
+        // the ignored swap file doesn't turn it into a config reload
+        assert!(matches!(
+            config_change_command(
+                &[scss.clone(), config_dir.join(".dynisland.scss.swp")],
+                config_dir,
+                &patterns
+            ),
+            Some(BackendServerCommand::ReloadCss)
+        ));
+        assert!(matches!(
+            config_change_command(&[scss, ron], config_dir, &patterns),
+            Some(BackendServerCommand::ReloadConfig { force: false, .. })
+        ));
+    }
+
+    #[test]
+    fn scss_prelude_defines_the_config_variables() {
+        let mut config = GeneralConfig {
+            minimal_height: 30,
+            minimal_width: 50,
+            blur_radius: 4.0,
+            ..Default::default()
+        };
+        config
+            .scss_variables
+            .insert("$radius".to_string(), " 12px ".to_string());
+        config
+            .scss_variables
+            .insert("accent".to_string(), "#ff0000".to_string());
+        // skipped, they would end the declaration or aren't identifiers
+        config
+            .scss_variables
+            .insert("bad".to_string(), "1px; } * { color: red".to_string());
+        config
+            .scss_variables
+            .insert("1st".to_string(), "1px".to_string());
+        // sorted by the key as written, so `$radius` comes before `accent`
+        assert_eq!(
+            scss_prelude(&config, Some(true)),
+            "$prefers-dark: true;\n\
+             $minimal-height: 30px;\n$minimal-width: 50px;\n$blur-radius: 4px;\n\
+             $radius: 12px;\n$accent: #ff0000;\n"
+        );
+        assert!(!scss_prelude(&config, None).contains("$prefers-dark"));
+    }
+
+    #[test]
+    fn slow_compiles_time_out_without_blocking_the_main_context() {
+        let context = glib::MainContext::new();
+        let ticked = Rc::new(Cell::new(false));
+        let tick = ticked.clone();
+        context.spawn_local(async move {
+            glib::timeout_future(Duration::from_millis(10)).await;
+            tick.set(true);
+        });
+        let start = Instant::now();
+        let result = context.block_on(compile_in_background(
+            || {
+                thread::sleep(Duration::from_secs(2));
+                Ok("late".to_string())
+            },
+            200,
+        ));
+        assert_eq!(result, Err("compile timed out after 200ms".to_string()));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // the other tasks kept running while waiting for the compile
+        assert!(ticked.get());
+    }
+
+    #[test]
+    fn compiles_within_the_timeout_are_used() {
+        let context = glib::MainContext::new();
+        let compile = || {
+            thread::sleep(Duration::from_millis(50));
+            Ok("window {}".to_string())
+        };
+        assert_eq!(
+            context.block_on(compile_in_background(compile, 5000)),
+            Ok("window {}".to_string())
+        );
+        // 0 waits for as long as it takes
+        assert_eq!(
+            context.block_on(compile_in_background(compile, 0)),
+            Ok("window {}".to_string())
+        );
+        assert_eq!(
+            context.block_on(compile_in_background(|| panic!("compile bug"), 0)),
+            Err("the compile thread panicked".to_string())
+        );
+    }
+
+    #[test]
+    fn scss_uses_the_prelude_variables() {
+        let scss_dir = std::env::temp_dir().join(format!(
+            "dynisland-test-{}-prelude-variables",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&scss_dir).unwrap();
+        std::fs::write(
+            scss_dir.join("dynisland.scss"),
+            "activity-widget { min-height: $minimal-height; margin: $gap; }\n",
+        )
+        .unwrap();
+        let mut config = GeneralConfig {
+            minimal_height: 42,
+            ..Default::default()
+        };
+        config
+            .scss_variables
+            .insert("$gap".to_string(), "2px * 3".to_string());
+
+        let css = compile_scss(
+            &scss_prelude(&config, None),
+            &scss_dir,
+            grass::OutputStyle::Compressed,
+        );
+        std::fs::remove_dir_all(&scss_dir).unwrap();
+        assert_eq!(
+            css.unwrap().trim(),
+            "activity-widget{min-height:42px;margin:6px}"
+        );
+    }
+
+    #[test]
+    fn filter_keeps_the_matching_activities() {
+        let clock = ActivityIdentifier::new("clock-module", "clock");
+        let player = ActivityIdentifier::new("music-module", "player");
+        let workspace = ActivityIdentifier::new("script-module", "workspace-1");
+        let activities = [
+            (clock.clone(), true),
+            (player.clone(), true),
+            (workspace.clone(), true),
+        ];
+
+        let pattern = glob::Pattern::new("*@clock-module").unwrap();
+        let (hidden, shown) = filter_changes(&activities, Some(&pattern), &HashSet::new());
+        assert!(hidden == HashSet::from([player.clone(), workspace.clone()]));
+        assert!(shown.is_empty());
+
+        // a new filter shows the activities it keeps
+        let activities = [
+            (clock.clone(), true),
+            (player.clone(), false),
+            (workspace.clone(), false),
+        ];
+        let pattern = glob::Pattern::new("workspace-*@*").unwrap();
+        let (hidden, shown) = filter_changes(&activities, Some(&pattern), &hidden);
+        assert!(hidden == HashSet::from([clock.clone(), player.clone()]));
+        assert!(shown == HashSet::from([workspace.clone()]));
+
+        // no filter shows the activities it hid
+        let (hidden, shown) = filter_changes(&activities, None, &HashSet::from([player.clone()]));
+        assert!(hidden.is_empty());
+        assert!(shown == HashSet::from([player]));
+    }
+
+    #[test]
+    fn filter_keeps_the_activities_hidden_by_other_commands() {
+        let clock = ActivityIdentifier::new("clock-module", "clock");
+        // hidden with set-visible, not by the filter
+        let activities = [(clock.clone(), false)];
+        let (hidden, shown) = filter_changes(&activities, None, &HashSet::new());
+        assert!(hidden.is_empty());
+        assert!(shown.is_empty());
+        let pattern = glob::Pattern::new("*@music-module").unwrap();
+        let (hidden, _) = filter_changes(&activities, Some(&pattern), &HashSet::new());
+        assert!(hidden.is_empty());
+    }
+
+    #[test]
+    fn crash_loop_at_the_restart_threshold() {
+        let reload = Instant::now();
+        let mut restarts = VecDeque::new();
+        let restart_at = |ms| reload + Duration::from_millis(ms);
+        // just below the threshold
+        for i in 1..CRASH_LOOP_RESTARTS as u64 {
+            assert!(!is_crash_loop(&mut restarts, reload, restart_at(i * 100)));
+        }
+        // at the threshold
+        assert!(is_crash_loop(&mut restarts, reload, restart_at(1000)));
+        assert!(restarts.is_empty());
+        // just above, the restarts are counted again from the revert
+        assert!(!is_crash_loop(&mut restarts, reload, restart_at(1100)));
+    }
+
+    #[test]
+    fn restarts_after_the_window_are_not_a_crash_loop() {
+        let reload = Instant::now();
+        let mut restarts = VecDeque::new();
+        for i in 1..CRASH_LOOP_RESTARTS as u32 {
+            assert!(!is_crash_loop(
+                &mut restarts,
+                reload,
+                reload + CRASH_LOOP_WINDOW * i / 4
+            ));
+        }
+        assert!(!is_crash_loop(
+            &mut restarts,
+            reload,
+            reload + CRASH_LOOP_WINDOW
+        ));
+        assert_eq!(restarts.len(), CRASH_LOOP_RESTARTS - 1);
+    }
+
+    #[test]
+    fn reloaded_css_drops_the_removed_rules() {
+        with_gtk(|| {
+            let mut app = App::default();
+            let display = gdk::Display::default().unwrap();
+            gtk::style_context_add_provider_for_display(
+                &display,
+                &app.css_provider,
+                app.config.general_style_config.css_priority.map_gtk(),
+            );
+            let label = gtk::Label::new(None);
+            label.add_css_class("swap-test");
+            let window = gtk::Window::new();
+            window.set_child(Some(&label));
+            let context = glib::MainContext::default();
+
+            app.swap_css_provider(".swap-test { color: rgb(255, 0, 0); }");
+            while context.iteration(false) {}
+            assert_eq!(label.color(), gdk::RGBA::RED);
+
+            app.swap_css_provider("");
+            while context.iteration(false) {}
+            assert_ne!(label.color(), gdk::RGBA::RED);
+
+            gtk::style_context_remove_provider_for_display(&display, &app.css_provider);
+            window.destroy();
+        });
+    }
+
+    #[test]
+    fn css_is_reloaded_when_the_config_is_unchanged() {
+        with_gtk(|| {
+            let config_dir = std::env::temp_dir().join(format!(
+                "dynisland-test-{}-unchanged-config",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&config_dir).unwrap();
+            std::fs::write(config_dir.join("dynisland.ron"), "()").unwrap();
+            std::fs::write(config_dir.join("dynisland.scss"), ".before { color: red; }").unwrap();
+            let mut app = App::default();
+            let (config, errors) = config::load_config(&config_dir, None);
+            assert!(errors.is_empty(), "{errors:?}");
+            app.config = config;
+            let context = glib::MainContext::default();
+            context.block_on(app.load_css(&config_dir)).unwrap();
+            assert!(app.last_css.contains(".before"));
+
+            // an scss save in the same debounce window as a touch of the config
+            std::fs::write(config_dir.join("dynisland.scss"), ".after { color: red; }").unwrap();
+            let (server_send, server_recv) = unbounded_channel();
+            let (response_send, mut response_recv) = unbounded_channel();
+            server_send
+                .send(BackendServerCommand::ReloadConfig {
+                    respond: true,
+                    force: false,
+                })
+                .unwrap();
+            server_send.send(BackendServerCommand::ReloadCss).unwrap();
+            server_send.send(BackendServerCommand::DumpCss).unwrap();
+            drop(server_send);
+            context.block_on(app.start_backend_server(
+                server_recv,
+                response_send,
+                config_dir.clone(),
+            ));
+            std::fs::remove_dir_all(&config_dir).unwrap();
+
+            assert_eq!(response_recv.try_recv(), Ok(None));
+            let css = response_recv.try_recv().unwrap().unwrap();
+            assert!(css.contains(".after"), "{css}");
+            assert!(!css.contains(".before"), "{css}");
+        });
+    }
+
+    #[test]
+    fn filtering_only_changes_the_visibility() {
+        with_gtk(|| {
+            let (layout, reflows) = counting_mock_layout();
+            let activities: Vec<(ActivityIdentifier, Widget)> = (0..3)
+                .map(|i| {
+                    let id = ActivityIdentifier::new("test-module", &format!("activity-{i}"));
+                    let widget = new_activity();
+                    add_activity(&layout, &id, &widget);
+                    (id, widget)
+                })
+                .collect();
+            assert_eq!(reflows.get(), 3);
+            reflows.set(0);
+
+            let app = App {
+                layout: Some(layout.clone()),
+                ..Default::default()
+            };
+            let (server_send, server_recv) = unbounded_channel();
+            let (response_send, mut response_recv) = unbounded_channel();
+            for pattern in ["activity-0@*", "activity-[01]@*"] {
+                server_send
+                    .send(BackendServerCommand::FilterActivities(Some(
+                        pattern.to_string(),
+                    )))
+                    .unwrap();
+            }
+            drop(server_send);
+            glib::MainContext::default().block_on(app.start_backend_server(
+                server_recv,
+                response_send,
+                std::env::temp_dir(),
+            ));
+
+            assert_eq!(response_recv.try_recv(), Ok(None));
+            assert_eq!(response_recv.try_recv(), Ok(None));
+            // hidden by the first filter and shown again by the second one
+            let visible: Vec<bool> = activities
+                .iter()
+                .map(|(_, widget)| widget.is_visible())
+                .collect();
+            assert_eq!(visible, [true, true, false]);
+            // the activities are never removed from the layout and added again
+            assert_eq!(reflows.get(), 0);
+            assert_eq!(
+                glib::MainContext::default()
+                    .block_on(layout.lock())
+                    .1
+                    .list_activities()
+                    .len(),
+                3
+            );
+        });
+    }
+
+    #[test]
+    fn queued_css_reloads_compile_once() {
+        let (server_send, mut server_recv) = unbounded_channel();
+        for i in 0..10 {
+            server_send.send(BackendServerCommand::ReloadCss).unwrap();
+            if i == 4 {
+                server_send.send(BackendServerCommand::Stats).unwrap();
+            }
+        }
+        server_send.send(BackendServerCommand::DumpCss).unwrap();
+
+        // the first reload is the one that compiles
+        assert!(matches!(
+            server_recv.try_recv(),
+            Ok(BackendServerCommand::ReloadCss)
+        ));
+        let mut deferred = VecDeque::new();
+        assert_eq!(coalesce_css_reloads(&mut server_recv, &mut deferred), 9);
+        assert!(server_recv.try_recv().is_err());
+        // the other commands are kept in order
+        assert!(matches!(
+            deferred.pop_front(),
+            Some(BackendServerCommand::Stats)
+        ));
+        assert!(matches!(
+            deferred.pop_front(),
+            Some(BackendServerCommand::DumpCss)
+        ));
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn producer_restarts_back_off() {
+        let restarts = ProducerRestarts::default();
+        assert_eq!(
+            producer_restart_decision(&restarts, "clock-module"),
+            RestartDecision::Now
+        );
+        for step in 0..PRODUCER_MAX_RESTARTS as u32 - 1 {
+            assert_eq!(
+                producer_restart_decision(&restarts, "clock-module"),
+                RestartDecision::After(PRODUCER_BACKOFF_BASE * 2u32.pow(step))
+            );
+        }
+        // the other modules have their own count
+        assert_eq!(
+            producer_restart_decision(&restarts, "music-module"),
+            RestartDecision::Now
+        );
+    }
+
+    #[test]
+    fn producer_restarts_are_disabled_after_the_limit() {
+        let restarts = ProducerRestarts::default();
+        for _ in 0..PRODUCER_MAX_RESTARTS {
+            assert_ne!(
+                producer_restart_decision(&restarts, "clock-module"),
+                RestartDecision::Disabled
+            );
+        }
+        assert_eq!(
+            producer_restart_decision(&restarts, "clock-module"),
+            RestartDecision::Disabled
+        );
+        // until the config is reloaded
+        assert_eq!(
+            producer_restart_decision(&restarts, "clock-module"),
+            RestartDecision::Disabled
+        );
+        restarts.borrow_mut().clear();
+        assert_eq!(
+            producer_restart_decision(&restarts, "clock-module"),
+            RestartDecision::Now
+        );
+    }
+
+    #[test]
+    fn old_producer_restarts_are_forgotten() {
+        let Some(old) = Instant::now().checked_sub(PRODUCER_RESTART_WINDOW * 2) else {
+            return;
+        };
+        let restarts = ProducerRestarts::default();
+        restarts.borrow_mut().insert(
+            "clock-module".to_string(),
+            Some(vec![old; PRODUCER_MAX_RESTARTS].into()),
+        );
+        assert_eq!(
+            producer_restart_decision(&restarts, "clock-module"),
+            RestartDecision::Now
+        );
+    }
+}
@@ -1,16 +1,44 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 
 #[derive(Parser, Debug, Serialize, Deserialize)]
 #[command(arg_required_else_help(true), version, about, long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
-    pub command: SubCommands,
+    pub command: Option<SubCommands>,
 
     #[arg(long, short)]
     pub config_path: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "config profile to use, defaults to the one matching $XDG_CURRENT_DESKTOP"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long,
+        help = "read dynisland.ron from stdin, the config dir is still used for the scss and the modules"
+    )]
+    pub config_stdin: bool,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "comma separated ron extensions to enable for the config: unwrap_newtypes, unwrap_variant_newtypes"
+    )]
+    pub ron_extensions: Vec<String>,
+
+    #[arg(long, help = "print the config directory that would be used and exit")]
+    pub print_config_path: bool,
+
+    #[arg(
+        long,
+        help = "print the runtime directory, with the socket and the log, and exit"
+    )]
+    pub print_state_path: bool,
 }
 
 #[derive(Subcommand, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -18,8 +46,37 @@ pub enum SubCommands {
     Daemon {
         #[arg(short, long, required = false, default_value_t = false)]
         no_daemonize: bool,
+        #[arg(
+            short,
+            long,
+            required = false,
+            default_value_t = false,
+            help = "open the gtk inspector at startup"
+        )]
+        inspector: bool,
+        #[arg(long, help = "ignore the config and scss, using the defaults")]
+        safe: bool,
+        #[arg(
+            long,
+            help = "render the activities once and exit, implies --no-daemonize"
+        )]
+        once: bool,
+        #[arg(
+            long,
+            default_value_t = 1000,
+            help = "milliseconds given to the modules to add their activities before rendering with --once"
+        )]
+        once_delay: u64,
+        #[arg(long, help = "save the first window to a png file, implies --once")]
+        screenshot: Option<PathBuf>,
+    },
+    Reload {
+        #[arg(
+            long,
+            help = "apply every section of the config and restart the producers, even if nothing changed"
+        )]
+        force: bool,
     },
-    Reload,
     Inspector,
     HealthCheck,
     ActivityNotification {
@@ -28,17 +85,87 @@ pub enum SubCommands {
         mode: u8,
         duration: Option<u64>,
     },
+    #[command(about = "Hide or show an activity without removing it")]
+    SetActivityVisible {
+        activity_identifier: String,
+        #[arg(action = ArgAction::Set)]
+        visible: bool,
+    },
+    #[command(
+        about = "Hide the activities that don't match the glob pattern, like `workspace-*@*`, replacing the previous filter"
+    )]
+    FilterActivities {
+        pattern: String,
+    },
+    #[command(about = "Show the activities hidden by filter-activities")]
+    ClearFilter,
+    #[command(about = "Replace the css classes previously set on an activity with this command")]
+    SetActivityClasses {
+        activity_identifier: String,
+        classes: Vec<String>,
+    },
+    #[command(about = "Print a field of general_style_config as json")]
+    GetGeneral {
+        field: String,
+    },
+    #[command(
+        about = "Set a field of general_style_config until the next reload, the value is parsed as json"
+    )]
+    SetGeneral {
+        field: String,
+        value: String,
+    },
+    #[command(
+        about = "Use the layout config named `<layout>.<profile>` in layout_configs, without a profile the base one"
+    )]
+    SetLayoutProfile {
+        profile: Option<String>,
+    },
     Kill,
     Restart {
         #[arg(short, long, required = false, default_value_t = false)]
         no_daemonize: bool,
+        #[arg(
+            short,
+            long,
+            required = false,
+            default_value_t = false,
+            help = "open the gtk inspector at startup"
+        )]
+        inspector: bool,
+        #[arg(long, help = "ignore the config and scss, using the defaults")]
+        safe: bool,
     },
     DefaultConfig {
         // #[arg(short, long, required = false, default_value_t = false)]
         #[arg(skip = false)]
         replace_current_config: bool,
     },
-    ListActivities,
+    #[command(about = "Print the embedded default scss, a starting point for dynisland.scss")]
+    DefaultCss,
+    #[command(
+        about = "Print where the config was loaded from and the errors found while loading it"
+    )]
+    Check,
+    #[command(about = "Print a JSON Schema of the config, for editor autocompletion")]
+    Schema,
+    ListActivities {
+        #[arg(
+            long,
+            help = "print the state of each activity as json, grouped by module"
+        )]
+        json: bool,
+    },
+    #[command(
+        about = "Run only this module, with the default FallbackLayout in a normal window and the inspector open"
+    )]
+    Dev {
+        module_name: String,
+    },
+    #[command(about = "Print runtime statistics as json")]
+    Stats,
+    #[command(about = "Print the last css compiled from dynisland.scss")]
+    DumpCss,
     Module {
         module_name: String,
         // #[arg(required = true, value_delimiter = ' ', num_args = 1..)]
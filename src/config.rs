@@ -1,32 +1,133 @@
 use std::{
     collections::HashMap,
     fmt::Display,
+    io::Read,
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
+use anyhow::Context;
 use dynisland_core::{
     abi::{glib, log},
     ron,
 };
 use ron::{extensions::Extensions, ser::PrettyConfig, Value};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub const CONFIG_REL_PATH: &str = "dynisland/";
+/// Set to [`get_data_dir`] at startup, so the modules can find it without depending on this crate
+pub const DATA_DIR_ENV: &str = "DYNISLAND_DATA_DIR";
+/// version of the config schema, bump it when adding a step to [`migrate`]
+pub const CONFIG_VERSION: u32 = 1;
+pub const MAX_BLUR_RADIUS: f64 = 50.0;
+pub const MAX_MINIMAL_SIZE: u32 = 1000;
+/// editor swap/backup files and vcs metadata
+pub const DEFAULT_IGNORE_PATTERNS: [&str; 8] = [
+    ".git", "*.swp", "*.swx", "*~", "4913", ".#*", "#*#", "*.tmp",
+];
 
 // ron sucks, ~~i need to switch to pkl~~
 // nvm, there are no good pkl crates
+// dynisland.pkl is supported with the pkl feature, through the pkl cli
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 #[serde(default)]
 pub struct Config {
-    pub loaded_modules: Vec<String>,
+    /// missing in configs written before the field existed, which are version 0
+    #[serde(default)]
+    pub version: u32,
+    pub loaded_modules: Vec<ModuleEntry>,
     pub layout: Option<String>,
     pub general_style_config: GeneralConfig,
+    /// keyed by layout name, `<layout>.<profile>` keys are used with `set-layout-profile`
+    #[schemars(with = "HashMap<String, serde_json::Value>")]
     pub layout_configs: HashMap<String, Value>,
+    #[schemars(with = "HashMap<String, serde_json::Value>")]
     pub module_config: HashMap<String, Value>,
     pub debug: Option<DebugConfig>,
+    /// overrides selected with `--profile` or by matching `$XDG_CURRENT_DESKTOP`
+    pub profiles: HashMap<String, ConfigProfile>,
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+
+/// Options that replace the ones in the base config when the profile is selected,
+/// `layout_configs` and `module_config` replace only the entries they contain
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, JsonSchema)]
+#[serde(default)]
+pub struct ConfigProfile {
+    pub loaded_modules: Option<Vec<ModuleEntry>>,
+    pub layout: Option<String>,
+    pub general_style_config: Option<GeneralConfig>,
+    #[schemars(with = "HashMap<String, serde_json::Value>")]
+    pub layout_configs: HashMap<String, Value>,
+    #[schemars(with = "HashMap<String, serde_json::Value>")]
+    pub module_config: HashMap<String, Value>,
+}
+
+/// An entry of `loaded_modules`,
+/// either the module name or the name and a condition that has to be satisfied to load it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum ModuleEntry {
+    Name(String),
+    Conditional {
+        name: String,
+        condition: ModuleCondition,
+    },
+}
+
+impl ModuleEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            ModuleEntry::Name(name) => name,
+            ModuleEntry::Conditional { name, .. } => name,
+        }
+    }
+
+    /// Returns the reason why the module shouldn't be loaded, if any
+    pub fn unmet_condition(&self) -> Option<String> {
+        match self {
+            ModuleEntry::Name(_) => None,
+            ModuleEntry::Conditional { condition, .. } => condition.unmet(),
+        }
+    }
+}
+
+/// Every specified check must pass for the module to be loaded
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
+#[serde(default)]
+pub struct ModuleCondition {
+    /// the environment variable must be set
+    pub env: Option<String>,
+    /// the file or directory must exist
+    pub file_exists: Option<String>,
+    /// the hostname must match (case insensitive)
+    pub hostname: Option<String>,
+}
+
+impl ModuleCondition {
+    fn unmet(&self) -> Option<String> {
+        if let Some(var) = &self.env {
+            if std::env::var_os(var).is_none() {
+                return Some(format!("environment variable {var} is not set"));
+            }
+        }
+        if let Some(path) = &self.file_exists {
+            if !Path::new(path).exists() {
+                return Some(format!("{path} doesn't exist"));
+            }
+        }
+        if let Some(hostname) = &self.hostname {
+            let current = glib::host_name();
+            if !current.eq_ignore_ascii_case(hostname) {
+                return Some(format!("hostname is {current}, not {hostname}"));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 #[serde(default)]
 pub struct DebugConfig {
     pub runtime_path: String,
@@ -42,16 +143,92 @@ impl Default for DebugConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 #[serde(default)]
 pub struct GeneralConfig {
     pub minimal_height: u32,
     pub minimal_width: u32,
     pub blur_radius: f64,
     pub enable_drag_stretch: bool,
+    /// animate the minimal height and the blur radius when they change on a reload
+    pub animate_general_changes: bool,
+    /// compile the scss in compressed style, the default expanded style keeps the rules readable in the gtk inspector
+    pub css_compressed: bool,
+    /// give up compiling the scss after this long and keep the previous css, 0 waits forever
+    pub css_compile_timeout_ms: u64,
+    /// only used on X11, where layer-shell isn't available
+    pub x11_window_type: X11WindowType,
+    /// export the `com.github.cr3eperall.dynisland` interface on the session bus
+    pub dbus: bool,
+    /// wait this long before removing an activity, re-adding it in the meantime cancels the removal
+    pub remove_debounce_ms: u64,
+    /// priority of the user's scss compared to the other style providers
+    pub css_priority: CssPriority,
+    /// wait before creating the windows, for compositors that aren't ready when dynisland starts
+    pub startup_delay_ms: u64,
+    /// wait until a display is available before creating the windows
+    pub wait_for_display: bool,
+    /// glob patterns of files in the config dir that shouldn't trigger a reload
+    pub ignore_patterns: Vec<String>,
+    /// reload when a file in the config dir changes, otherwise only signals and ipc reload it.
+    /// checked at startup
+    pub watch_config: bool,
+    /// opacity of the windows after `inactive_delay_ms` without the pointer on them, 1.0 disables it
+    pub inactive_opacity: f64,
+    pub inactive_delay_ms: u64,
+    /// define `$prefers-dark` in the scss from the settings portal and recompile it when it changes,
+    /// checked at startup
+    pub follow_color_scheme: bool,
+    /// start the producers after the first frame is drawn, so the windows show up sooner
+    pub defer_producers_until_shown: bool,
+    /// keep the windows hidden until an activity is added, or for at most 5 seconds,
+    /// disabling it while waiting shows the windows
+    pub hide_until_first_activity: bool,
+    /// border radius of the activities in px, unset uses the one from the scss
+    pub corner_radius: Option<f64>,
+    /// border width of the activities in px, unset uses the one from the scss
+    pub border_width: Option<f64>,
+    /// any css color, unset uses the one from the scss
+    pub border_color: Option<String>,
+    /// put the layer-shell windows on the `Overlay` layer, above fullscreen windows.
+    /// GTK4 can't set keep-above on X11, there the window manager decides
+    pub keep_above: bool,
+    /// log the activities that got less space than their minimum size, to debug the scss
+    pub warn_size_issues: bool,
+    /// icon theme used by the modules that load icons by name, unset uses the one from the gtk settings
+    pub icon_theme: Option<String>,
+    /// defined as `$name: value;` before `dynisland.scss`, after the built-in
+    /// `$minimal-height`, `$minimal-width` and `$blur-radius` so they can be overridden
+    pub scss_variables: HashMap<String, String>,
+    /// run with `sh -c` after a config or css reload succeeds
+    pub post_reload_command: Option<String>,
+    /// run with `sh -c` after a config or css reload fails, the errors are in `$DYNISLAND_RELOAD_ERROR`
+    pub on_reload_error_command: Option<String>,
     // pub hide_widget_timeout_ms: u32,
 }
 
+/// How the windows are shown to X11 window managers.
+///
+/// GTK4 can't set `_NET_WM_WINDOW_TYPE`, so the window manager decides the stacking
+/// and fullscreen windows can cover the windows. The hints GTK4 has only keep them off taskbars and pagers
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "X11WindowType")]
+pub enum X11WindowType {
+    /// hidden from taskbars and pagers, `dock` and `utility` from older configs do the same
+    #[default]
+    #[serde(
+        alias = "auto",
+        alias = "Dock",
+        alias = "dock",
+        alias = "Utility",
+        alias = "utility"
+    )]
+    Auto,
+    /// no hints, the windows are shown like the other applications
+    #[serde(alias = "normal")]
+    Normal,
+}
+
 impl Default for GeneralConfig {
     fn default() -> Self {
         Self {
@@ -59,30 +236,169 @@ impl Default for GeneralConfig {
             minimal_width: 60,
             blur_radius: 6.0,
             enable_drag_stretch: false, // whether to enable stretching widgets by dragging
-                                        // hide_widget_timeout_ms: 1000,
-                                        //TODO find a way to add scrolling label to settings
+            animate_general_changes: false,
+            css_compressed: false,
+            css_compile_timeout_ms: 5000,
+            x11_window_type: X11WindowType::Auto,
+            dbus: false,
+            remove_debounce_ms: 0,
+            css_priority: CssPriority::User,
+            startup_delay_ms: 0,
+            wait_for_display: false,
+            ignore_patterns: DEFAULT_IGNORE_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            watch_config: true,
+            inactive_opacity: 1.0,
+            inactive_delay_ms: 3000,
+            follow_color_scheme: false,
+            defer_producers_until_shown: false,
+            hide_until_first_activity: false,
+            corner_radius: None,
+            border_width: None,
+            border_color: None,
+            keep_above: false,
+            warn_size_issues: false,
+            icon_theme: None,
+            scss_variables: HashMap::new(),
+            post_reload_command: None,
+            on_reload_error_command: None,
+            // hide_widget_timeout_ms: 1000,
+            //TODO find a way to add scrolling label to settings
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "CssPriority")]
+pub enum CssPriority {
+    #[serde(alias = "settings")]
+    Settings,
+    #[serde(alias = "theme")]
+    Theme,
+    #[serde(alias = "application")]
+    Application,
+    #[default]
+    #[serde(alias = "user")]
+    User,
+}
+
+impl CssPriority {
+    pub fn map_gtk(&self) -> u32 {
+        match self {
+            CssPriority::Settings => gtk::STYLE_PROVIDER_PRIORITY_SETTINGS,
+            CssPriority::Theme => gtk::STYLE_PROVIDER_PRIORITY_THEME,
+            CssPriority::Application => gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+            CssPriority::User => gtk::STYLE_PROVIDER_PRIORITY_USER,
+        }
+    }
+}
+
+impl GeneralConfig {
+    /// Clamps the values that would render a broken or very expensive widget.
+    /// Returns a warning for each value it changed, they are logged too
+    pub fn validate(&mut self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut warn = |warning: String| {
+            log::warn!("{warning}");
+            warnings.push(warning);
+        };
+        if self.blur_radius.is_nan() {
+            warn("blur_radius is not a number, using the default".to_string());
+            self.blur_radius = GeneralConfig::default().blur_radius;
+        } else if !(0.0..=MAX_BLUR_RADIUS).contains(&self.blur_radius) {
+            let clamped = self.blur_radius.clamp(0.0, MAX_BLUR_RADIUS);
+            warn(format!(
+                "blur_radius {} is out of range (0.0-{MAX_BLUR_RADIUS}), using {clamped}",
+                self.blur_radius
+            ));
+            self.blur_radius = clamped;
+        }
+        if self.minimal_height > MAX_MINIMAL_SIZE {
+            warn(format!(
+                "minimal_height {} is too big, using {MAX_MINIMAL_SIZE}",
+                self.minimal_height
+            ));
+            self.minimal_height = MAX_MINIMAL_SIZE;
+        }
+        if self.minimal_width > MAX_MINIMAL_SIZE {
+            warn(format!(
+                "minimal_width {} is too big, using {MAX_MINIMAL_SIZE}",
+                self.minimal_width
+            ));
+            self.minimal_width = MAX_MINIMAL_SIZE;
+        }
+        if self.inactive_opacity.is_nan() {
+            warn("inactive_opacity is not a number, using the default".to_string());
+            self.inactive_opacity = GeneralConfig::default().inactive_opacity;
+        } else if !(0.0..=1.0).contains(&self.inactive_opacity) {
+            let clamped = self.inactive_opacity.clamp(0.0, 1.0);
+            warn(format!(
+                "inactive_opacity {} is out of range (0.0-1.0), using {clamped}",
+                self.inactive_opacity
+            ));
+            self.inactive_opacity = clamped;
+        }
+        for (name, value) in [
+            ("corner_radius", &mut self.corner_radius),
+            ("border_width", &mut self.border_width),
+        ] {
+            if value.is_some_and(|value| value.is_nan() || value < 0.0) {
+                warn(format!(
+                    "{name} must be a positive number, using the one from the scss"
+                ));
+                *value = None;
+            }
+        }
+        warnings
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         let module_map = HashMap::<String, Value>::new();
         let layout_map = HashMap::<String, Value>::new();
         Self {
+            version: CONFIG_VERSION,
             module_config: module_map,
             layout_configs: layout_map,
             layout: Some("FallbackLayout".to_string()),
             general_style_config: GeneralConfig::default(),
-            loaded_modules: vec!["all".to_string()],
+            loaded_modules: vec![ModuleEntry::Name("all".to_string())],
             debug: None,
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Keeps only `module_name` and its config, with the default FallbackLayout in a normal window.
+    /// Used by `dynisland dev`
+    pub fn restricted_to_module(mut self, module_name: &str) -> Self {
+        let window_config = ron::from_str("(window_position: (layer_shell: false))")
+            .expect("invalid dev layout config");
+        Config {
+            loaded_modules: vec![ModuleEntry::Name(module_name.to_string())],
+            layout: Some(crate::layout_manager::NAME.to_string()),
+            layout_configs: HashMap::from([(
+                crate::layout_manager::NAME.to_string(),
+                window_config,
+            )]),
+            module_config: self
+                .module_config
+                .remove_entry(module_name)
+                .into_iter()
+                .collect(),
+            debug: self.debug,
+            ..Config::default()
         }
     }
 }
 
 impl Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let options = ron_options();
         let res = options
             .to_string_pretty(self, PrettyConfig::default())
             .unwrap_or("unable to parse config".to_string());
@@ -97,6 +413,38 @@ impl Config {
             .map(|debug| PathBuf::from(debug.runtime_path))
             .unwrap_or(get_default_runtime_path())
     }
+
+    /// Returns the requested profile, or the first one matching a desktop in `$XDG_CURRENT_DESKTOP`
+    pub fn select_profile(&self, requested: Option<&str>) -> Option<String> {
+        if let Some(requested) = requested {
+            if self.profiles.contains_key(requested) {
+                return Some(requested.to_string());
+            }
+            log::warn!("profile {requested} not found, using the base config");
+            return None;
+        }
+        let desktops = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        desktops.split(':').find_map(|desktop| {
+            self.profiles
+                .keys()
+                .find(|name| name.eq_ignore_ascii_case(desktop))
+                .cloned()
+        })
+    }
+
+    pub fn merge(&mut self, profile: ConfigProfile) {
+        if let Some(loaded_modules) = profile.loaded_modules {
+            self.loaded_modules = loaded_modules;
+        }
+        if let Some(layout) = profile.layout {
+            self.layout = Some(layout);
+        }
+        if let Some(general_style_config) = profile.general_style_config {
+            self.general_style_config = general_style_config;
+        }
+        self.layout_configs.extend(profile.layout_configs);
+        self.module_config.extend(profile.module_config);
+    }
 }
 
 pub fn get_default_config_path() -> PathBuf {
@@ -105,24 +453,610 @@ pub fn get_default_config_path() -> PathBuf {
 fn get_default_runtime_path() -> PathBuf {
     glib::user_runtime_dir().join(CONFIG_REL_PATH)
 }
+pub fn get_data_dir() -> PathBuf {
+    glib::user_data_dir().join(CONFIG_REL_PATH)
+}
+
+/// extensions enabled with `--ron-extensions`, on top of `implicit_some`
+static RON_EXTENSIONS: OnceLock<Extensions> = OnceLock::new();
+
+/// Enables the named ron extensions for every config that is parsed or printed after this.
+/// `#![enable(...)]` at the top of the file works too, this avoids repeating it in every file
+pub fn set_ron_extensions(names: &[String]) -> anyhow::Result<()> {
+    let mut extensions = Extensions::empty();
+    for name in names {
+        extensions |= match name.as_str() {
+            "implicit_some" => Extensions::IMPLICIT_SOME,
+            "unwrap_newtypes" => Extensions::UNWRAP_NEWTYPES,
+            "unwrap_variant_newtypes" => Extensions::UNWRAP_VARIANT_NEWTYPES,
+            _ => anyhow::bail!(
+                "unknown ron extension {name}, available: implicit_some, unwrap_newtypes, unwrap_variant_newtypes"
+            ),
+        };
+    }
+    let _ = RON_EXTENSIONS.set(extensions);
+    Ok(())
+}
 
-pub fn get_config(config_dir: &Path) -> Config {
-    let config_path = config_dir.join("dynisland.ron");
-    let content = std::fs::read_to_string(config_path);
-    let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+/// Options used to parse and print the config, printing with the same ones keeps it parseable
+pub fn ron_options() -> ron::Options {
+    let extensions = RON_EXTENSIONS.get().copied().unwrap_or(Extensions::empty());
+    ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME | extensions)
+}
+
+/// content of the config read with `--config-stdin`, used instead of `dynisland.ron`
+static STDIN_CONFIG: OnceLock<String> = OnceLock::new();
+
+/// Reads the whole standard input as the config, every following load uses it instead of the config file
+pub fn read_config_from_stdin() -> std::io::Result<()> {
+    let mut content = String::new();
+    std::io::stdin().read_to_string(&mut content)?;
+    let _ = STDIN_CONFIG.set(content);
+    Ok(())
+}
 
-    let ron: Config = match content {
-        Ok(content) => options.from_str(&content).unwrap_or_else(|err| {
-            log::warn!(
-                "failed to parse config, using default. Err:{}",
-                err.to_string()
-            );
+pub fn config_from_stdin() -> bool {
+    STDIN_CONFIG.get().is_some()
+}
+
+pub fn get_config(config_dir: &Path, profile: Option<&str>) -> Config {
+    load_config(config_dir, profile).0
+}
+
+/// Like [`get_config`], also returns the errors that were logged while loading
+pub fn load_config(config_dir: &Path, profile: Option<&str>) -> (Config, Vec<String>) {
+    let mut errors = Vec::new();
+    let pkl_path = config_dir.join("dynisland.pkl");
+    let mut ron: Config = if let Some(content) = STDIN_CONFIG.get() {
+        let options = ron_options();
+        options.from_str(content).unwrap_or_else(|err| {
+            log::warn!("failed to parse config from stdin, using the default for the invalid fields. Err:{err}");
+            errors.push(format!("failed to parse config: {err}"));
+            parse_config_fields(&options, content, &mut errors)
+        })
+    } else if cfg!(feature = "pkl") && pkl_path.exists() {
+        eval_pkl_config(&pkl_path).unwrap_or_else(|err| {
+            log::warn!("failed to evaluate dynisland.pkl, using default: {err:#}");
+            errors.push(format!("failed to evaluate dynisland.pkl: {err:#}"));
             Config::default()
-        }),
+        })
+    } else {
+        let config_path = config_dir.join("dynisland.ron");
+        let content = std::fs::read_to_string(config_path);
+        let options = ron_options();
+
+        match content {
+            Ok(content) => options.from_str(&content).unwrap_or_else(|err| {
+                log::warn!(
+                    "failed to parse config, using the default for the invalid fields. Err:{}",
+                    err.to_string()
+                );
+                errors.push(format!("failed to parse config: {err}"));
+                parse_config_fields(&options, &content, &mut errors)
+            }),
+            Err(err) => {
+                log::warn!("failed to parse config file, using default: {err}");
+                errors.push(format!("failed to read config file: {err}"));
+                Config::default()
+            }
+        }
+    };
+    if ron.version > CONFIG_VERSION {
+        log::warn!(
+            "config version {} is newer than the supported version {CONFIG_VERSION}, some options may be ignored",
+            ron.version
+        );
+    } else if ron.version < CONFIG_VERSION {
+        ron = migrate(ron);
+    }
+    if let Some(name) = ron.select_profile(profile) {
+        log::info!("using config profile {name}");
+        let profile = ron.profiles[&name].clone();
+        ron.merge(profile);
+    }
+    // reported like the errors, so `check` and the reload response show them
+    errors.extend(ron.general_style_config.validate());
+    for value in ron
+        .module_config
+        .values_mut()
+        .chain(ron.layout_configs.values_mut())
+    {
+        resolve_includes(value, config_dir, &mut errors);
+    }
+    (ron, errors)
+}
+
+/// Describes every place the config could have come from, whether it existed and parsed,
+/// and the order in which the used ones were applied, for `dynisland check` and the debug log.
+///
+/// `config` is the one returned by [`load_config`], used to report the selected profile
+pub fn config_sources(config_dir: &Path, config: &Config, profile: Option<&str>) -> Vec<String> {
+    let mut report = Vec::new();
+    let mut order = vec!["defaults".to_string()];
+    let pkl_path = config_dir.join("dynisland.pkl");
+    let ron_path = config_dir.join("dynisland.ron");
+    if config_from_stdin() {
+        report.push("stdin: used (--config-stdin)".to_string());
+        order.push("stdin".to_string());
+    }
+    let pkl_used = !config_from_stdin() && cfg!(feature = "pkl") && pkl_path.exists();
+    report.push(format!(
+        "{}: {}",
+        pkl_path.display(),
+        if !cfg!(feature = "pkl") {
+            "ignored, built without the pkl feature".to_string()
+        } else if !pkl_path.exists() {
+            "missing".to_string()
+        } else if config_from_stdin() {
+            "exists, not used".to_string()
+        } else {
+            match eval_pkl_config(&pkl_path) {
+                Ok(_) => "exists, evaluated, used".to_string(),
+                Err(err) => format!("exists, failed to evaluate, using the defaults: {err:#}"),
+            }
+        }
+    ));
+    if pkl_used {
+        order.push(pkl_path.display().to_string());
+    }
+    let ron_status = match std::fs::read_to_string(&ron_path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => "missing".to_string(),
+        Err(err) => format!("exists, failed to read: {err}"),
+        Ok(_) if config_from_stdin() || pkl_used => "exists, not used".to_string(),
+        Ok(content) => {
+            order.push(ron_path.display().to_string());
+            let options = ron_options();
+            match options.from_str::<Config>(&content) {
+                Ok(_) => "exists, parsed, used".to_string(),
+                Err(err) => {
+                    format!("exists, failed to parse, the invalid fields use their defaults: {err}")
+                }
+            }
+        }
+    };
+    report.push(format!("{}: {ron_status}", ron_path.display()));
+    match profile {
+        Some(requested) if config.profiles.contains_key(requested) => {
+            report.push(format!("profile {requested}: selected with --profile"));
+            order.push(format!("profile {requested}"));
+        }
+        Some(requested) => report.push(format!(
+            "profile {requested}: not found, using the base config"
+        )),
+        None => match config.select_profile(None) {
+            Some(name) => {
+                report.push(format!("profile {name}: matched $XDG_CURRENT_DESKTOP"));
+                order.push(format!("profile {name}"));
+            }
+            None => report.push("profile: none selected".to_string()),
+        },
+    }
+    report.push(format!("applied in order: {}", order.join(" < ")));
+    report
+}
+
+/// Parses each top-level field on its own, so an invalid one is replaced with its default
+/// without losing the others. A syntax error still gives the default config
+fn parse_config_fields(options: &ron::Options, content: &str, errors: &mut Vec<String>) -> Config {
+    let fields = match options.from_str::<Value>(content) {
+        Ok(Value::Map(fields)) => fields,
+        Ok(_) => {
+            log::warn!("the config is not a struct, using default");
+            errors.push("the config is not a struct, using default".to_string());
+            return Config::default();
+        }
         Err(err) => {
-            log::warn!("failed to parse config file, using default: {err}");
-            Config::default()
+            log::warn!("the config has a syntax error, using default: {err}");
+            return Config::default();
         }
     };
-    ron
+    let mut valid_fields = serde_json::Map::new();
+    for (name, value) in fields {
+        let Value::String(name) = name else {
+            continue;
+        };
+        let value = ron_to_json(value);
+        let single_field =
+            serde_json::Value::Object([(name.clone(), value.clone())].into_iter().collect());
+        match serde_json::from_value::<Config>(single_field) {
+            Ok(_) => {
+                valid_fields.insert(name, value);
+            }
+            Err(err) => {
+                log::warn!("invalid config field {name}, using its default: {err}");
+                errors.push(format!("invalid config field {name}: {err}"));
+            }
+        }
+    }
+    serde_json::from_value(serde_json::Value::Object(valid_fields)).unwrap_or_default()
+}
+
+/// Converts a ron value to json, struct and enum names are lost,
+/// options become their content, like `IMPLICIT_SOME` would allow
+fn ron_to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Bool(bool) => bool.into(),
+        Value::Char(char) => char.to_string().into(),
+        Value::String(string) => string.into(),
+        Value::Number(ron::Number::Integer(int)) => int.into(),
+        Value::Number(ron::Number::Float(float)) => serde_json::Number::from_f64(float.get())
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Option(None) | Value::Unit => serde_json::Value::Null,
+        Value::Option(Some(value)) => ron_to_json(*value),
+        Value::Seq(seq) => seq.into_iter().map(ron_to_json).collect(),
+        Value::Map(map) => map
+            .into_iter()
+            .map(|(key, value)| {
+                let key = match key {
+                    Value::String(key) => key,
+                    key => ron_to_json(key).to_string(),
+                };
+                (key, ron_to_json(value))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+/// Evaluates the file with the `pkl` cli, which has to be in `$PATH`, and parses the json output
+fn eval_pkl_config(path: &Path) -> anyhow::Result<Config> {
+    let output = std::process::Command::new("pkl")
+        .args(["eval", "--format", "json"])
+        .arg(path)
+        .output()
+        .context("failed to run pkl")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "pkl eval failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// JSON Schema of [`Config`], with `layout` and the names in `loaded_modules` restricted to the given ones.
+///
+/// `layout_configs` and `module_config` are left as open objects, they are defined by the modules
+pub fn json_schema(module_names: &[String], layout_names: &[String]) -> serde_json::Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(Config)).unwrap();
+    let mut module_names = module_names.to_vec();
+    module_names.push("all".to_string());
+    module_names.sort();
+    module_names.dedup();
+    let mut layout_names = layout_names.to_vec();
+    layout_names.sort();
+    layout_names.dedup();
+    if let Some(definitions) = schema["definitions"].as_object_mut() {
+        definitions.insert(
+            "ModuleName".to_string(),
+            serde_json::json!({ "type": "string", "enum": module_names }),
+        );
+        definitions.insert(
+            "LayoutName".to_string(),
+            serde_json::json!({ "type": "string", "enum": layout_names }),
+        );
+        definitions.insert(
+            "ModuleEntry".to_string(),
+            serde_json::json!({
+                "anyOf": [
+                    { "$ref": "#/definitions/ModuleName" },
+                    {
+                        "type": "object",
+                        "required": ["name", "condition"],
+                        "properties": {
+                            "name": { "$ref": "#/definitions/ModuleName" },
+                            "condition": { "$ref": "#/definitions/ModuleCondition" },
+                        },
+                    },
+                ],
+            }),
+        );
+    }
+    if let Some(layout) = schema["properties"]["layout"].as_object_mut() {
+        layout.remove("type");
+        layout.insert(
+            "anyOf".to_string(),
+            serde_json::json!([{ "$ref": "#/definitions/LayoutName" }, { "type": "null" }]),
+        );
+    }
+    schema
+}
+
+/// Upgrades a config written for an older schema version.
+///
+/// Renamed fields are handled with `#[serde(alias)]`,
+/// a step is needed when the meaning or the default of an option changes
+pub fn migrate(mut config: Config) -> Config {
+    for version in config.version..CONFIG_VERSION {
+        log::info!("migrating config from version {version} to {}", version + 1);
+        match version {
+            // the version field was added, nothing else changed
+            0 => {}
+            _ => unreachable!(),
+        }
+    }
+    config.version = CONFIG_VERSION;
+    config
+}
+
+/// Replaces every string value of the form `$include("path")` or `$secret("path")`
+/// with the content of the referenced file, relative paths are resolved from the config dir.
+/// A file that can't be read leaves the value empty and is reported in `errors`
+fn resolve_includes(value: &mut Value, config_dir: &Path, errors: &mut Vec<String>) {
+    match value {
+        Value::String(string) => {
+            let Some(path) = parse_include(string) else {
+                return;
+            };
+            let path = config_dir.join(path);
+            *string = match std::fs::read_to_string(&path) {
+                Ok(content) => content.trim_end_matches(['\n', '\r']).to_string(),
+                Err(err) => {
+                    log::error!("failed to read included file {}: {err}", path.display());
+                    errors.push(format!(
+                        "failed to read included file {}: {err}",
+                        path.display()
+                    ));
+                    String::new()
+                }
+            };
+        }
+        Value::Map(map) => {
+            for value in map.values_mut() {
+                resolve_includes(value, config_dir, errors);
+            }
+        }
+        Value::Seq(seq) => {
+            for value in seq.iter_mut() {
+                resolve_includes(value, config_dir, errors);
+            }
+        }
+        Value::Option(Some(value)) => resolve_includes(value, config_dir, errors),
+        _ => {}
+    }
+}
+
+fn parse_include(string: &str) -> Option<&str> {
+    let args = string
+        .trim()
+        .strip_prefix("$include(")
+        .or_else(|| string.trim().strip_prefix("$secret("))?
+        .strip_suffix(')')?
+        .trim();
+    args.strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_x11_window_types_hide_from_the_taskbar() {
+        for name in ["Auto", "dock", "Dock", "utility", "Utility"] {
+            let window_type: X11WindowType =
+                serde_json::from_str(&format!(r#"{{"X11WindowType": "{name}"}}"#)).unwrap();
+            assert_eq!(window_type, X11WindowType::Auto, "{name}");
+        }
+        let window_type: X11WindowType =
+            serde_json::from_str(r#"{"X11WindowType": "normal"}"#).unwrap();
+        assert_eq!(window_type, X11WindowType::Normal);
+    }
+
+    fn include_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dynisland-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(dir.join("secrets")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes_are_read_relative_to_the_config_dir() {
+        let config_dir = include_dir("relative-include");
+        std::fs::write(config_dir.join("secrets/token"), "hunter2\n").unwrap();
+        let mut value = Value::String(r#"$secret("secrets/token")"#.to_string());
+        let mut errors = Vec::new();
+        resolve_includes(&mut value, &config_dir, &mut errors);
+        std::fs::remove_dir_all(&config_dir).unwrap();
+        assert_eq!(value, Value::String("hunter2".to_string()));
+        assert!(errors.is_empty(), "{errors:?}");
+        // only the exact directive is replaced
+        let mut value = Value::String("token: $include(\"secrets/token\")".to_string());
+        resolve_includes(&mut value, &config_dir, &mut errors);
+        assert_eq!(
+            value,
+            Value::String("token: $include(\"secrets/token\")".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_includes_are_reported() {
+        let config_dir = include_dir("missing-include");
+        let mut value = Value::String(r#"$include("secrets/missing")"#.to_string());
+        let mut errors = Vec::new();
+        resolve_includes(&mut value, &config_dir, &mut errors);
+        std::fs::remove_dir_all(&config_dir).unwrap();
+        assert_eq!(value, Value::String(String::new()));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("secrets/missing"), "{errors:?}");
+    }
+
+    #[test]
+    fn nested_includes_are_resolved() {
+        let config_dir = include_dir("nested-include");
+        std::fs::write(config_dir.join("secrets/token"), "hunter2").unwrap();
+        let mut value: Value = ron_options()
+            .from_str(
+                r#"(api: (tokens: ["plain", "$include(\"secrets/token\")"]), key: Some("$secret(\"secrets/token\")"))"#,
+            )
+            .unwrap();
+        let mut errors = Vec::new();
+        resolve_includes(&mut value, &config_dir, &mut errors);
+        std::fs::remove_dir_all(&config_dir).unwrap();
+        let expected: Value = ron_options()
+            .from_str(r#"(api: (tokens: ["plain", "hunter2"]), key: Some("hunter2"))"#)
+            .unwrap();
+        assert_eq!(value, expected);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped_and_reported() {
+        let mut config = GeneralConfig {
+            blur_radius: 600.0,
+            minimal_height: MAX_MINIMAL_SIZE + 1,
+            minimal_width: 5000,
+            inactive_opacity: -0.5,
+            corner_radius: Some(-1.0),
+            border_width: Some(f64::NAN),
+            ..Default::default()
+        };
+        let warnings = config.validate();
+        assert_eq!(config.blur_radius, MAX_BLUR_RADIUS);
+        assert_eq!(config.minimal_height, MAX_MINIMAL_SIZE);
+        assert_eq!(config.minimal_width, MAX_MINIMAL_SIZE);
+        assert_eq!(config.inactive_opacity, 0.0);
+        assert_eq!(config.corner_radius, None);
+        assert_eq!(config.border_width, None);
+        assert_eq!(warnings.len(), 6, "{warnings:?}");
+        assert!(warnings[0].starts_with("blur_radius 600"));
+
+        let mut config = GeneralConfig {
+            blur_radius: -3.0,
+            ..Default::default()
+        };
+        assert_eq!(config.validate().len(), 1);
+        assert_eq!(config.blur_radius, 0.0);
+        config.blur_radius = f64::NAN;
+        config.validate();
+        assert_eq!(config.blur_radius, GeneralConfig::default().blur_radius);
+    }
+
+    #[test]
+    fn values_in_range_are_kept() {
+        let mut config = GeneralConfig {
+            blur_radius: MAX_BLUR_RADIUS,
+            minimal_height: MAX_MINIMAL_SIZE,
+            inactive_opacity: 1.0,
+            corner_radius: Some(0.0),
+            ..Default::default()
+        };
+        let expected = config.clone();
+        assert!(config.validate().is_empty());
+        assert_eq!(config, expected);
+        assert!(GeneralConfig::default().validate().is_empty());
+    }
+
+    fn conditional(condition: ModuleCondition) -> ModuleEntry {
+        ModuleEntry::Conditional {
+            name: "battery-module".to_string(),
+            condition,
+        }
+    }
+
+    #[test]
+    fn module_conditions() {
+        // not set with set_var, the tests run in parallel
+        let (set_var, _) = std::env::vars_os()
+            .next()
+            .expect("no environment variables");
+        let set_var = set_var.to_string_lossy().to_string();
+        let unset_var = "DYNISLAND_TEST_VARIABLE_THAT_IS_NOT_SET".to_string();
+        let existing_file = env!("CARGO_MANIFEST_DIR").to_string();
+        let missing_file = format!("{existing_file}/does-not-exist");
+        let hostname = glib::host_name().to_uppercase();
+
+        assert_eq!(
+            ModuleEntry::Name("clock-module".to_string()).unmet_condition(),
+            None
+        );
+        assert_eq!(
+            conditional(ModuleCondition::default()).unmet_condition(),
+            None
+        );
+        let met = ModuleCondition {
+            env: Some(set_var),
+            file_exists: Some(existing_file),
+            // case insensitive
+            hostname: Some(hostname.clone()),
+        };
+        assert_eq!(conditional(met.clone()).unmet_condition(), None);
+
+        let unmet = conditional(ModuleCondition {
+            env: Some(unset_var.clone()),
+            ..met.clone()
+        });
+        assert_eq!(
+            unmet.unmet_condition(),
+            Some(format!("environment variable {unset_var} is not set"))
+        );
+        let unmet = conditional(ModuleCondition {
+            file_exists: Some(missing_file.clone()),
+            ..met.clone()
+        });
+        assert_eq!(
+            unmet.unmet_condition(),
+            Some(format!("{missing_file} doesn't exist"))
+        );
+        let unmet = conditional(ModuleCondition {
+            hostname: Some(format!("not-{hostname}")),
+            ..met
+        });
+        assert!(unmet
+            .unmet_condition()
+            .is_some_and(|reason| reason.starts_with("hostname is")));
+    }
+
+    #[test]
+    fn conditional_entries_parse() {
+        let entries: Vec<ModuleEntry> = ron_options()
+            .from_str(
+                r#"["clock-module", (name: "battery-module", condition: (file_exists: "/sys/class/power_supply/BAT0"))]"#,
+            )
+            .unwrap();
+        assert_eq!(entries[0], ModuleEntry::Name("clock-module".to_string()));
+        assert_eq!(
+            entries[1],
+            conditional(ModuleCondition {
+                file_exists: Some("/sys/class/power_supply/BAT0".to_string()),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_fields_keep_the_other_sections() {
+        let content = r#"(
+            loaded_modules: ["clock-module"],
+            layout: "FlowLayout",
+            general_style_config: (minimal_height: 50),
+            module_config: ["not", "a", "map"],
+        )"#;
+        let mut errors = Vec::new();
+        let config = parse_config_fields(&ron_options(), content, &mut errors);
+        assert_eq!(config.general_style_config.minimal_height, 50);
+        assert_eq!(config.layout.as_deref(), Some("FlowLayout"));
+        assert_eq!(
+            config.loaded_modules,
+            [ModuleEntry::Name("clock-module".to_string())]
+        );
+        assert!(config.module_config.is_empty());
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with("invalid config field module_config"));
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let content = r#"(
+            layout: "FlowLayout",
+            general_style_config: (minimal_height: "tall"),
+            loaded_modules: 3,
+        )"#;
+        let mut errors = Vec::new();
+        let config = parse_config_fields(&ron_options(), content, &mut errors);
+        assert_eq!(config.layout.as_deref(), Some("FlowLayout"));
+        assert_eq!(config.general_style_config, GeneralConfig::default());
+        assert_eq!(config.loaded_modules, Config::default().loaded_modules);
+        assert_eq!(errors.len(), 2, "{errors:?}");
+    }
 }
@@ -0,0 +1,196 @@
+use anyhow::{anyhow, Result};
+use dynisland_core::abi::log;
+use gtk::{gio, glib, prelude::*};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::app::BackendServerCommand;
+
+pub const INTERFACE_NAME: &str = "com.github.cr3eperall.dynisland";
+pub const OBJECT_PATH: &str = "/com/github/cr3eperall/dynisland";
+
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_SETTINGS: &str = "org.freedesktop.portal.Settings";
+const APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+
+const INTERFACE_XML: &str = r#"
+<node>
+    <interface name="com.github.cr3eperall.dynisland">
+        <method name="ReloadConfig"/>
+        <method name="ReloadCss"/>
+        <signal name="Reloaded">
+            <arg name="success" type="b"/>
+            <arg name="error" type="s"/>
+        </signal>
+    </interface>
+</node>
+"#;
+
+/// Exports the dynisland interface on the connection the application is registered with.
+///
+/// The methods are forwarded to the backend server like the socket commands.
+pub fn register_object(
+    application: &gtk::Application,
+    server_send: UnboundedSender<BackendServerCommand>,
+) -> Result<gio::RegistrationId> {
+    let connection = application
+        .dbus_connection()
+        .ok_or_else(|| anyhow!("application is not registered on the session bus"))?;
+    let node = gio::DBusNodeInfo::for_xml(INTERFACE_XML)?;
+    let interface = node
+        .lookup_interface(INTERFACE_NAME)
+        .ok_or_else(|| anyhow!("missing interface {INTERFACE_NAME}"))?;
+    let id = connection
+        .register_object(OBJECT_PATH, &interface)
+        .method_call(move |_, _, _, _, method, _, invocation| {
+            let command = match method {
+                "ReloadConfig" => BackendServerCommand::ReloadConfig {
+                    respond: false,
+                    force: false,
+                },
+                "ReloadCss" => BackendServerCommand::ReloadCss,
+                _ => {
+                    invocation.return_error(
+                        gio::IOErrorEnum::NotSupported,
+                        &format!("unknown method: {method}"),
+                    );
+                    return;
+                }
+            };
+            log::debug!("DBus method called: {method}");
+            match server_send.send(command) {
+                Ok(()) => invocation.return_value(None),
+                Err(err) => invocation.return_error(gio::IOErrorEnum::Failed, &err.to_string()),
+            }
+        })
+        .build()?;
+    log::info!("exported DBus interface {INTERFACE_NAME} at {OBJECT_PATH}");
+    Ok(id)
+}
+
+/// Emits the `Reloaded` signal with the errors of the reload, if any.
+/// Does nothing if the application has no DBus connection
+pub fn emit_reloaded(application: &gtk::Application, error: Option<&str>) {
+    let connection = match application.dbus_connection() {
+        Some(connection) => connection,
+        None => return,
+    };
+    let args = reloaded_args(error);
+    if let Err(err) =
+        connection.emit_signal(None, OBJECT_PATH, INTERFACE_NAME, "Reloaded", Some(&args))
+    {
+        log::warn!("failed to emit Reloaded signal: {err}");
+    }
+}
+
+/// `(success, error)`, the error is empty if the reload succeeded
+fn reloaded_args(error: Option<&str>) -> glib::Variant {
+    (error.is_none(), error.unwrap_or_default()).to_variant()
+}
+
+/// Sends `ColorSchemeChanged` with the color scheme from the settings portal,
+/// once at startup and every time it changes
+pub fn watch_color_scheme(
+    application: &gtk::Application,
+    server_send: UnboundedSender<BackendServerCommand>,
+) -> Result<gio::SignalSubscriptionId> {
+    let connection = application
+        .dbus_connection()
+        .ok_or_else(|| anyhow!("application is not registered on the session bus"))?;
+    let read_send = server_send.clone();
+    connection.call(
+        Some(PORTAL_NAME),
+        PORTAL_PATH,
+        PORTAL_SETTINGS,
+        "Read",
+        Some(&(APPEARANCE_NAMESPACE, "color-scheme").to_variant()),
+        None,
+        gio::DBusCallFlags::NONE,
+        -1,
+        None::<&gio::Cancellable>,
+        move |result| match result.map(|reply| unwrap_u32(&reply)) {
+            Ok(Some(scheme)) => {
+                let _ = read_send.send(BackendServerCommand::ColorSchemeChanged(scheme == 1));
+            }
+            Ok(None) => log::warn!("unexpected color-scheme value from the settings portal"),
+            Err(err) => {
+                log::warn!("failed to read the color scheme from the settings portal: {err}")
+            }
+        },
+    );
+    let id = connection.signal_subscribe(
+        Some(PORTAL_NAME),
+        Some(PORTAL_SETTINGS),
+        Some("SettingChanged"),
+        Some(PORTAL_PATH),
+        None,
+        gio::DBusSignalFlags::NONE,
+        move |_, _, _, _, _, params| {
+            let Some((namespace, key, value)) = params.get::<(String, String, glib::Variant)>()
+            else {
+                return;
+            };
+            if namespace != APPEARANCE_NAMESPACE || key != "color-scheme" {
+                return;
+            }
+            if let Some(scheme) = unwrap_u32(&value) {
+                // 0: no preference, 1: dark, 2: light
+                let _ = server_send.send(BackendServerCommand::ColorSchemeChanged(scheme == 1));
+            }
+        },
+    );
+    Ok(id)
+}
+
+/// `Read` wraps the value in a variant once or twice depending on the portal version
+fn unwrap_u32(variant: &glib::Variant) -> Option<u32> {
+    if let Some(value) = variant.get::<u32>() {
+        return Some(value);
+    }
+    if let Some(inner) = variant.as_variant() {
+        return unwrap_u32(&inner);
+    }
+    if variant.is_container() && variant.n_children() == 1 {
+        return unwrap_u32(&variant.child_value(0));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloaded_reports_the_errors() {
+        assert_eq!(
+            reloaded_args(None).get::<(bool, String)>(),
+            Some((true, String::new()))
+        );
+        assert_eq!(
+            reloaded_args(Some("failed to parse css")).get::<(bool, String)>(),
+            Some((false, "failed to parse css".to_string()))
+        );
+    }
+
+    #[test]
+    fn interface_has_the_reloaded_signal() {
+        let node = gio::DBusNodeInfo::for_xml(INTERFACE_XML).unwrap();
+        let interface = node.lookup_interface(INTERFACE_NAME).unwrap();
+        assert!(interface.lookup_signal("Reloaded").is_some());
+        assert!(interface.lookup_method("ReloadConfig").is_some());
+        assert!(interface.lookup_method("ReloadCss").is_some());
+    }
+
+    #[test]
+    fn color_scheme_is_unwrapped() {
+        assert_eq!(unwrap_u32(&1u32.to_variant()), Some(1));
+        assert_eq!(
+            unwrap_u32(&glib::Variant::from_variant(&1u32.to_variant())),
+            Some(1)
+        );
+        // wrapped twice by the older portals
+        let twice = glib::Variant::from_variant(&glib::Variant::from_variant(&2u32.to_variant()));
+        assert_eq!(unwrap_u32(&(twice,).to_variant()), Some(2));
+        assert_eq!(unwrap_u32(&"dark".to_variant()), None);
+    }
+}
@@ -12,15 +12,36 @@ use dynisland_core::{
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{UnixListener, UnixStream},
-    sync::mpsc::UnboundedSender,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
 };
 
-use crate::{app::BackendServerCommand, cli::SubCommands};
+use crate::{
+    app::{BackendServerCommand, RELOAD_DEBOUNCE},
+    cli::SubCommands,
+    config::GeneralConfig,
+};
+
+/// Used as the scss compile time when `css_compile_timeout_ms` is 0
+const UNLIMITED_COMPILE_WAIT: Duration = Duration::from_secs(60);
+
+/// How long the commands other than a reload wait for the response of the backend server
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// How long a reload can take: the debounce, the scss compile and applying the config.
+/// Waiting less would leave the response in the channel, where it would answer the next command
+pub fn reload_timeout(config: &GeneralConfig) -> Duration {
+    let compile = match config.css_compile_timeout_ms {
+        0 => UNLIMITED_COMPILE_WAIT,
+        timeout_ms => Duration::from_millis(timeout_ms),
+    };
+    RELOAD_DEBOUNCE + compile + Duration::from_secs(5)
+}
 
 pub async fn open_socket(
     runtime_path: &Path,
     server_send: UnboundedSender<BackendServerCommand>,
-    server_response_recv: &mut tokio::sync::mpsc::UnboundedReceiver<Option<String>>,
+    server_response_recv: &mut UnboundedReceiver<Option<String>>,
+    reload_timeout: Duration,
 ) -> Result<()> {
     let _ = std::fs::remove_file(runtime_path.join("dynisland.sock"));
     let listener = UnixListener::bind(runtime_path.join("dynisland.sock"))?;
@@ -28,92 +49,110 @@ pub async fn open_socket(
         let (mut stream, _socket) = listener.accept().await?;
         let message = read_message(&mut stream).await?;
         log::debug!("IPC message received: {message:?}");
-        match message {
-            SubCommands::Reload => {
-                server_send.send(BackendServerCommand::ReloadConfig)?;
-            }
-            SubCommands::Inspector => {
-                server_send.send(BackendServerCommand::OpenInspector)?;
-                if let Ok(Some(response)) =
-                    tokio::time::timeout(Duration::from_millis(800), server_response_recv.recv())
-                        .await
-                {
-                    let _ = send_response(&mut stream, response).await;
-                }
-            }
-            SubCommands::Kill => {
-                server_send.send(BackendServerCommand::Stop)?;
-                if let Ok(Some(response)) =
-                    tokio::time::timeout(Duration::from_millis(800), server_response_recv.recv())
-                        .await
-                {
-                    let _ = send_response(&mut stream, response).await;
-                }
-                break;
-            }
+        // a response that came after its command timed out isn't the answer to this one
+        while server_response_recv.try_recv().is_ok() {
+            log::debug!("dropping a late response to a previous command");
+        }
+        let command = match message {
+            SubCommands::Reload { force } => BackendServerCommand::ReloadConfig {
+                respond: true,
+                force,
+            },
+            SubCommands::Inspector => BackendServerCommand::OpenInspector,
+            SubCommands::Kill => BackendServerCommand::Stop,
             SubCommands::HealthCheck => {
                 log::info!("received HealthCheck, Everything OK");
                 let _ = send_response(&mut stream, None).await;
+                stream.shutdown().await?;
+                continue;
             }
             SubCommands::ActivityNotification {
                 activity_identifier,
                 mode,
                 duration,
             } => {
-                let components: Vec<&str> = activity_identifier.split('@').collect();
-                if components.len() != 2 {
-                    log::error!("invalid activity identifier: {activity_identifier}");
+                let id = match parse_activity_identifier(&activity_identifier) {
+                    Ok(id) => id,
+                    Err(err) => {
+                        log::error!("{err}");
+                        let _ = send_response(&mut stream, Some(err.to_string())).await;
+                        stream.shutdown().await?;
+                        continue;
+                    }
+                };
+                let mode = ActivityMode::try_from(mode).map_err(|e| anyhow!(e))?;
+                BackendServerCommand::ActivityNotification(id, mode, duration)
+            }
+            SubCommands::SetActivityVisible {
+                activity_identifier,
+                visible,
+            } => match parse_activity_identifier(&activity_identifier) {
+                Ok(id) => BackendServerCommand::SetActivityVisible(id, visible),
+                Err(err) => {
+                    let _ = send_response(&mut stream, Some(err.to_string())).await;
+                    stream.shutdown().await?;
                     continue;
                 }
-                let id = ActivityIdentifier::new(components[1], components[0]);
-                let mode = ActivityMode::try_from(mode).map_err(|e| anyhow!(e))?;
-                server_send.send(BackendServerCommand::ActivityNotification(
-                    id, mode, duration,
-                ))?;
-                if let Ok(Some(response)) =
-                    tokio::time::timeout(Duration::from_millis(800), server_response_recv.recv())
-                        .await
-                {
-                    let _ = send_response(&mut stream, response).await;
+            },
+            SubCommands::SetActivityClasses {
+                activity_identifier,
+                classes,
+            } => match parse_activity_identifier(&activity_identifier) {
+                Ok(id) => BackendServerCommand::SetActivityClasses(id, classes),
+                Err(err) => {
+                    let _ = send_response(&mut stream, Some(err.to_string())).await;
+                    stream.shutdown().await?;
+                    continue;
                 }
+            },
+            SubCommands::GetGeneral { field } => BackendServerCommand::GetGeneral(field),
+            SubCommands::SetGeneral { field, value } => {
+                BackendServerCommand::SetGeneral(field, value)
             }
-            SubCommands::ListActivities => {
-                server_send.send(BackendServerCommand::ListActivities)?;
-                if let Ok(Some(response)) =
-                    tokio::time::timeout(Duration::from_millis(800), server_response_recv.recv())
-                        .await
-                {
-                    let _ = send_response(&mut stream, response).await;
-                }
+            SubCommands::FilterActivities { pattern } => {
+                BackendServerCommand::FilterActivities(Some(pattern))
             }
-            SubCommands::Module { module_name, args } => {
-                server_send.send(BackendServerCommand::ModuleCliCommand(
-                    module_name,
-                    args.join(" "),
-                ))?;
-                if let Ok(Some(response)) =
-                    tokio::time::timeout(Duration::from_millis(800), server_response_recv.recv())
-                        .await
-                {
-                    let _ = send_response(&mut stream, response).await;
-                }
+            SubCommands::ClearFilter => BackendServerCommand::FilterActivities(None),
+            SubCommands::SetLayoutProfile { profile } => {
+                BackendServerCommand::SetLayoutProfile(profile)
             }
-            SubCommands::Layout { args } => {
-                server_send.send(BackendServerCommand::LayoutCliCommand(args.join(" ")))?;
-                if let Ok(Some(response)) =
-                    tokio::time::timeout(Duration::from_millis(800), server_response_recv.recv())
-                        .await
-                {
-                    let _ = send_response(&mut stream, response).await;
-                }
+            SubCommands::ListActivities { json } => BackendServerCommand::ListActivities(json),
+            SubCommands::DumpCss => BackendServerCommand::DumpCss,
+            SubCommands::Stats => BackendServerCommand::Stats,
+            SubCommands::Module { module_name, args } => {
+                BackendServerCommand::ModuleCliCommand(module_name, args.join(" "))
             }
+            SubCommands::Layout { args } => BackendServerCommand::LayoutCliCommand(args.join(" ")),
             SubCommands::DefaultConfig {
                 replace_current_config: _,
             }
-            | SubCommands::Daemon { no_daemonize: _ }
-            | SubCommands::Restart { no_daemonize: _ } => {
+            | SubCommands::DefaultCss
+            | SubCommands::Schema
+            | SubCommands::Check
+            | SubCommands::Daemon { .. }
+            | SubCommands::Dev { .. }
+            | SubCommands::Restart { .. } => {
                 log::error!("invalid message passed to ipc");
+                stream.shutdown().await?;
+                continue;
             }
+        };
+        // reloading the modules and compiling the scss can take a while
+        let timeout = match command {
+            BackendServerCommand::ReloadConfig { .. } => reload_timeout,
+            _ => RESPONSE_TIMEOUT,
+        };
+        let stop = matches!(command, BackendServerCommand::Stop);
+        send_and_wait(
+            &mut stream,
+            &server_send,
+            server_response_recv,
+            command,
+            timeout,
+        )
+        .await?;
+        if stop {
+            break;
         }
         stream.shutdown().await?;
     }
@@ -121,6 +160,32 @@ pub async fn open_socket(
     Ok(())
 }
 
+/// Sends the command to the backend server and forwards its response,
+/// nothing is sent if it doesn't respond within `timeout`
+async fn send_and_wait(
+    stream: &mut UnixStream,
+    server_send: &UnboundedSender<BackendServerCommand>,
+    server_response_recv: &mut UnboundedReceiver<Option<String>>,
+    command: BackendServerCommand,
+    timeout: Duration,
+) -> Result<()> {
+    server_send.send(command)?;
+    if let Ok(Some(response)) = tokio::time::timeout(timeout, server_response_recv.recv()).await {
+        let _ = send_response(stream, response).await;
+    }
+    Ok(())
+}
+
+/// Parses an activity identifier written in the same format printed by `list-activities`
+pub fn parse_activity_identifier(identifier: &str) -> Result<ActivityIdentifier> {
+    match identifier.split('@').collect::<Vec<&str>>()[..] {
+        [activity, module] if !activity.is_empty() && !module.is_empty() => {
+            Ok(ActivityIdentifier::new(module, activity))
+        }
+        _ => Err(anyhow!("invalid activity identifier: {identifier}")),
+    }
+}
+
 pub async fn read_message(stream: &mut UnixStream) -> Result<SubCommands> {
     let mut message_len_bytes = [0u8; 4];
     stream.read_exact(&mut message_len_bytes).await?;
@@ -142,15 +207,21 @@ pub async fn send_response(stream: &mut UnixStream, message: Option<String>) ->
 pub fn send_recv_message(
     mut stream: std::os::unix::net::UnixStream,
     message: &SubCommands,
+    config: &GeneralConfig,
 ) -> Result<Option<String>> {
     stream.set_nonblocking(false)?;
+    // the daemon waits up to `reload_timeout` for a reload to finish
+    let timeout = match message {
+        SubCommands::Reload { .. } => reload_timeout(config) + Duration::from_secs(1),
+        _ => Duration::from_millis(1000),
+    };
 
     let message = bincode::serialize(&message)?;
     let message_len_bytes = (message.len() as u32).to_be_bytes();
     stream.write_all(&message_len_bytes)?;
     stream.write_all(&message)?;
     let mut buf = Vec::new();
-    stream.set_read_timeout(Some(Duration::from_millis(1000)))?;
+    stream.set_read_timeout(Some(timeout))?;
     stream.read_to_end(&mut buf)?;
 
     Ok(if buf.is_empty() {
@@ -160,3 +231,40 @@ pub fn send_recv_message(
         Some(buf)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn activity_identifiers_are_parsed() {
+        let id = parse_activity_identifier("clock@clock-module").unwrap();
+        assert!(id == ActivityIdentifier::new("clock-module", "clock"));
+        // the same format printed by list-activities
+        assert_eq!(
+            parse_activity_identifier(&id.to_string())
+                .unwrap()
+                .to_string(),
+            id.to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_activity_identifiers_are_refused() {
+        for identifier in [
+            "clock",
+            "clock-module",
+            "",
+            "@",
+            "@clock-module",
+            "clock@",
+            "a@b@c",
+        ] {
+            let err = parse_activity_identifier(identifier).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("invalid activity identifier: {identifier}")
+            );
+        }
+    }
+}
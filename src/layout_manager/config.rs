@@ -14,7 +14,24 @@ pub struct FallbackLayoutConfigMain {
     pub(crate) orientation_horizontal: bool,
     pub(crate) window_position: WindowPosition,
     pub(crate) auto_minimize_timeout: i32,
+    /// gap between the activities in px
+    pub(crate) spacing: i32,
+    /// space around the activities in px
+    pub(crate) padding: i32,
+    /// css classes added to the windows, next to `dynisland`
+    pub(crate) css_classes: Vec<String>,
     pub(crate) windows: HashMap<String, FallbackLayoutConfig>,
+    /// group name -> activities (`activity@module`) that are put in a shared container.
+    /// Here and in the maps below activities can also be glob patterns, the most specific one is used
+    pub(crate) groups: HashMap<String, Vec<String>>,
+    /// activity (`activity@module`) -> shell commands to run on gestures
+    pub(crate) actions: HashMap<String, ActivityActions>,
+    /// activity (`activity@module`) -> depth, activities above 0 are stacked over the others, higher on top
+    pub(crate) z_index: HashMap<String, i32>,
+    /// activity (`activity@module`) -> priority, higher comes first in its row, the default is 0
+    pub(crate) priority: HashMap<String, i32>,
+    /// activities that always come first in their row, in this order, regardless of the priority
+    pub(crate) pinned: Vec<String>,
 }
 
 impl Default for FallbackLayoutConfigMain {
@@ -25,7 +42,15 @@ impl Default for FallbackLayoutConfigMain {
             orientation_horizontal: true,
             window_position: WindowPosition::default(),
             auto_minimize_timeout: DEFAULT_AUTO_MINIMIZE_TIMEOUT,
+            spacing: 0,
+            padding: 0,
+            css_classes: Vec::new(),
             windows: map,
+            groups: HashMap::new(),
+            actions: HashMap::new(),
+            z_index: HashMap::new(),
+            priority: HashMap::new(),
+            pinned: Vec::new(),
         }
     }
 }
@@ -35,6 +60,9 @@ impl FallbackLayoutConfigMain {
             orientation_horizontal: self.orientation_horizontal,
             window_position: self.window_position.clone(),
             auto_minimize_timeout: self.auto_minimize_timeout,
+            spacing: self.spacing,
+            padding: self.padding,
+            css_classes: self.css_classes.clone(),
         }
     }
     pub fn get_for_window(&self, window: &str) -> FallbackLayoutConfig {
@@ -43,6 +71,69 @@ impl FallbackLayoutConfigMain {
             None => self.default_conf(),
         }
     }
+    pub fn get_group(&self, activity: &str) -> Option<String> {
+        self.groups
+            .iter()
+            .flat_map(|(group, members)| members.iter().map(move |member| (member, group)))
+            .filter_map(|(member, group)| {
+                activity_match(member, activity).map(|specificity| (specificity, member, group))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+            .map(|(_, _, group)| group.clone())
+    }
+    pub fn get_actions(&self, activity: &str) -> Option<&ActivityActions> {
+        lookup_activity(&self.actions, activity)
+    }
+    pub fn get_z_index(&self, activity: &str) -> i32 {
+        lookup_activity(&self.z_index, activity)
+            .copied()
+            .unwrap_or(0)
+    }
+    pub fn get_priority(&self, activity: &str) -> i32 {
+        lookup_activity(&self.priority, activity)
+            .copied()
+            .unwrap_or(0)
+    }
+    /// position of the activity in `pinned`, an exact name is used before the patterns
+    pub fn get_pinned(&self, activity: &str) -> Option<usize> {
+        self.pinned
+            .iter()
+            .position(|pinned| pinned == activity)
+            .or_else(|| {
+                self.pinned
+                    .iter()
+                    .position(|pinned| activity_match(pinned, activity).is_some())
+            })
+    }
+}
+
+/// Returns how specific `key` is for the activity, if it matches it.
+///
+/// Keys are `activity@module` names or glob patterns like `workspace-*@*`,
+/// an exact name beats every pattern and patterns with more literal characters beat the others
+fn activity_match(key: &str, activity: &str) -> Option<(bool, usize)> {
+    if key == activity {
+        return Some((true, key.len()));
+    }
+    let pattern = glob::Pattern::new(key).ok()?;
+    pattern.matches(activity).then(|| {
+        let literals = key
+            .chars()
+            .filter(|c| !matches!(c, '*' | '?' | '[' | ']'))
+            .count();
+        (false, literals)
+    })
+}
+
+/// Finds the value of the most specific key matching the activity, see [`activity_match`]
+fn lookup_activity<'a, T>(map: &'a HashMap<String, T>, activity: &str) -> Option<&'a T> {
+    map.iter()
+        .filter_map(|(key, value)| {
+            activity_match(key, activity).map(|specificity| (specificity, key, value))
+        })
+        // ties are broken by the key so the result doesn't depend on the map order
+        .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+        .map(|(_, _, value)| value)
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -51,6 +142,9 @@ pub struct FallbackLayoutConfig {
     pub(crate) orientation_horizontal: bool,
     pub(crate) window_position: WindowPosition,
     pub(crate) auto_minimize_timeout: i32,
+    pub(crate) spacing: i32,
+    pub(crate) padding: i32,
+    pub(crate) css_classes: Vec<String>,
 }
 impl Default for FallbackLayoutConfig {
     fn default() -> Self {
@@ -58,6 +152,9 @@ impl Default for FallbackLayoutConfig {
             orientation_horizontal: true,
             window_position: WindowPosition::default(),
             auto_minimize_timeout: DEFAULT_AUTO_MINIMIZE_TIMEOUT,
+            spacing: 0,
+            padding: 0,
+            css_classes: Vec::new(),
         }
     }
 }
@@ -67,7 +164,15 @@ pub struct DeFallbackLayoutConfigMain {
     orientation_horizontal: bool,
     window_position: WindowPosition,
     auto_minimize_timeout: i32,
+    spacing: i32,
+    padding: i32,
+    css_classes: Vec<String>,
     windows: HashMap<String, DeFallbackLayoutConfig>,
+    groups: HashMap<String, Vec<String>>,
+    actions: HashMap<String, ActivityActions>,
+    z_index: HashMap<String, i32>,
+    priority: HashMap<String, i32>,
+    pinned: Vec<String>,
 }
 
 impl Default for DeFallbackLayoutConfigMain {
@@ -76,7 +181,15 @@ impl Default for DeFallbackLayoutConfigMain {
             orientation_horizontal: true,
             window_position: WindowPosition::default(),
             auto_minimize_timeout: DEFAULT_AUTO_MINIMIZE_TIMEOUT,
+            spacing: 0,
+            padding: 0,
+            css_classes: Vec::new(),
             windows: HashMap::new(),
+            groups: HashMap::new(),
+            actions: HashMap::new(),
+            z_index: HashMap::new(),
+            priority: HashMap::new(),
+            pinned: Vec::new(),
         }
     }
 }
@@ -110,6 +223,9 @@ impl DeFallbackLayoutConfigMain {
                     layer_shell: opt_window_pos
                         .layer_shell
                         .unwrap_or(self.window_position.layer_shell),
+                    keyboard_mode: opt_window_pos
+                        .keyboard_mode
+                        .unwrap_or(self.window_position.keyboard_mode),
                 },
                 None => self.window_position.clone(),
             };
@@ -121,6 +237,11 @@ impl DeFallbackLayoutConfigMain {
                 auto_minimize_timeout: opt_conf
                     .auto_minimize_timeout
                     .unwrap_or(self.auto_minimize_timeout),
+                spacing: opt_conf.spacing.unwrap_or(self.spacing),
+                padding: opt_conf.padding.unwrap_or(self.padding),
+                css_classes: opt_conf
+                    .css_classes
+                    .unwrap_or_else(|| self.css_classes.clone()),
             };
 
             windows.insert(name, conf);
@@ -129,7 +250,15 @@ impl DeFallbackLayoutConfigMain {
             orientation_horizontal: self.orientation_horizontal,
             window_position: self.window_position,
             auto_minimize_timeout: self.auto_minimize_timeout,
+            spacing: self.spacing,
+            padding: self.padding,
+            css_classes: self.css_classes,
             windows,
+            groups: self.groups,
+            actions: self.actions,
+            z_index: self.z_index,
+            priority: self.priority,
+            pinned: self.pinned,
         };
         if main_conf.windows.is_empty() {
             let default = main_conf.default_conf();
@@ -145,4 +274,124 @@ pub struct DeFallbackLayoutConfig {
     orientation_horizontal: Option<bool>,
     window_position: Option<DeWindowPosition>,
     auto_minimize_timeout: Option<i32>,
+    spacing: Option<i32>,
+    padding: Option<i32>,
+    css_classes: Option<Vec<String>>,
+}
+
+/// Shell commands run with `sh -c`, `dynisland` subcommands can be used to control the daemon
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ActivityActions {
+    pub(crate) click: Option<String>,
+    pub(crate) right_click: Option<String>,
+    pub(crate) scroll_up: Option<String>,
+    pub(crate) scroll_down: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FlowLayoutConfig {
+    pub(crate) window_position: WindowPosition,
+    pub(crate) auto_minimize_timeout: i32,
+    /// activities per row before wrapping, when there is enough width
+    pub(crate) max_children_per_line: u32,
+    pub(crate) min_children_per_line: u32,
+    pub(crate) row_spacing: u32,
+    pub(crate) column_spacing: u32,
+}
+
+impl Default for FlowLayoutConfig {
+    fn default() -> Self {
+        Self {
+            window_position: WindowPosition::default(),
+            auto_minimize_timeout: DEFAULT_AUTO_MINIMIZE_TIMEOUT,
+            max_children_per_line: 7,
+            min_children_per_line: 1,
+            row_spacing: 0,
+            column_spacing: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn exact_names_beat_patterns() {
+        assert_eq!(
+            activity_match("clock@clock-module", "clock@clock-module"),
+            Some((true, 18))
+        );
+        assert_eq!(
+            activity_match("*@clock-module", "clock@clock-module"),
+            Some((false, 13))
+        );
+        assert_eq!(activity_match("*@music-module", "clock@clock-module"), None);
+        assert!(
+            activity_match("clock@clock-module", "clock@clock-module")
+                > activity_match("clock@*", "clock@clock-module")
+        );
+        // more literal characters are more specific
+        assert!(
+            activity_match("workspace-*@*", "workspace-1@script-module")
+                > activity_match("*@*", "workspace-1@script-module")
+        );
+    }
+
+    #[test]
+    fn the_most_specific_group_is_used() {
+        let config = FallbackLayoutConfigMain {
+            groups: HashMap::from([
+                ("all".to_string(), strings(&["*@*"])),
+                ("workspaces".to_string(), strings(&["workspace-*@*"])),
+                ("music".to_string(), strings(&["player@music-module"])),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.get_group("workspace-2@script-module").as_deref(),
+            Some("workspaces")
+        );
+        assert_eq!(
+            config.get_group("player@music-module").as_deref(),
+            Some("music")
+        );
+        assert_eq!(
+            config.get_group("clock@clock-module").as_deref(),
+            Some("all")
+        );
+        assert_eq!(
+            FallbackLayoutConfigMain::default().get_group("clock@clock-module"),
+            None
+        );
+    }
+
+    #[test]
+    fn pinned_exact_names_come_before_the_patterns() {
+        let config = FallbackLayoutConfigMain {
+            pinned: strings(&[
+                "*@clock-module",
+                "player@music-module",
+                "clock@clock-module",
+            ]),
+            priority: HashMap::from([
+                ("*@*".to_string(), 1),
+                ("player@music-module".to_string(), 5),
+            ]),
+            ..Default::default()
+        };
+        // the exact name is found even though the pattern before it matches too
+        assert_eq!(config.get_pinned("clock@clock-module"), Some(2));
+        assert_eq!(config.get_pinned("timer@clock-module"), Some(0));
+        assert_eq!(config.get_pinned("player@music-module"), Some(1));
+        assert_eq!(config.get_pinned("workspace-1@script-module"), None);
+        assert_eq!(config.get_priority("player@music-module"), 5);
+        assert_eq!(config.get_priority("workspace-1@script-module"), 1);
+    }
 }
@@ -12,7 +12,7 @@ use abi_stable::{
 use anyhow::Result;
 use dynisland_core::{
     abi::{
-        abi_stable, gdk, glib, gtk,
+        abi_stable, gdk, glib, gtk, gtk_layer_shell,
         layout::{LayoutManagerType, SabiLayoutManager, SabiLayoutManager_TO},
         log,
         module::ActivityIdentifier,
@@ -24,18 +24,24 @@ use dynisland_core::{
 use gdk::prelude::*;
 use glib::SourceId;
 use gtk::{prelude::*, ApplicationWindow, EventController, StateFlags};
+use gtk_layer_shell::LayerShell;
 use ron::ser::PrettyConfig;
 
 use crate::layout_manager::{
     self,
     config::{DeFallbackLayoutConfigMain, FallbackLayoutConfigMain},
+    window_position::KeyboardMode,
 };
 
 pub struct FallbackLayout {
     app: gtk::Application,
     windows_containers: HashMap<String, (ApplicationWindow, gtk::Box)>,
+    /// (window name, group name) -> group container
+    group_containers: HashMap<(String, String), gtk::Box>,
     widget_map: HashMap<ActivityIdentifier, ActivityWidget>,
     cancel_minimize: Rc<RefCell<HashMap<ActivityIdentifier, SourceId>>>,
+    /// window name -> classes from `css_classes` on the window, removed when they change
+    window_classes: HashMap<String, Vec<String>>,
     config: FallbackLayoutConfigMain,
 }
 
@@ -45,8 +51,10 @@ pub extern "C" fn new(app: SabiApplication) -> RResult<LayoutManagerType, RBoxEr
     let this = FallbackLayout {
         app,
         windows_containers: HashMap::new(),
+        group_containers: HashMap::new(),
         widget_map: HashMap::new(),
         cancel_minimize: Rc::new(RefCell::new(HashMap::new())),
+        window_classes: HashMap::new(),
         config: FallbackLayoutConfigMain::default(),
     };
     ROk(SabiLayoutManager_TO::from_value(this, TD_CanDowncast))
@@ -82,10 +90,12 @@ impl SabiLayoutManager for FallbackLayout {
             }
         }
         self.configure_containers();
+        self.update_window_classes();
 
         for (id, widget) in self.widget_map.iter() {
             self.configure_widget(id, widget);
         }
+        self.update_groups();
 
         ROk(())
     }
@@ -107,6 +117,11 @@ impl SabiLayoutManager for FallbackLayout {
                 return;
             }
         };
+        widget.add_css_class(&format!("module-{}", css_class_name(&activity_id.module())));
+        widget.add_css_class(&format!(
+            "activity-{}",
+            css_class_name(&activity_id.activity())
+        ));
         self.configure_widget(activity_id, &widget);
         self.add_activity_to_container(activity_id, &widget);
         self.widget_map.insert(activity_id.clone(), widget);
@@ -168,6 +183,103 @@ impl SabiLayoutManager for FallbackLayout {
             );
         }
     }
+    fn cli_command(&self, args: RString) -> RResult<RString, RBoxError> {
+        match args.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["groups"] => {
+                let mut groups = Vec::new();
+                for ((window_name, group), container) in self.group_containers.iter() {
+                    let members: Vec<String> = container
+                        .observe_children()
+                        .iter::<glib::Object>()
+                        .flatten()
+                        .filter_map(|child| {
+                            let widget = child.downcast::<gtk::Widget>().ok()?;
+                            self.widget_map
+                                .iter()
+                                .find(|(_, w)| *w == &widget)
+                                .map(|(id, _)| id.to_string())
+                        })
+                        .collect();
+                    groups.push(format!(
+                        "{group} (window \"{window_name}\"): {}",
+                        members.join(", ")
+                    ));
+                }
+                groups.sort();
+                ROk(RString::from(groups.join("\n")))
+            }
+            ["priorities"] => {
+                let mut priorities: Vec<((Option<usize>, i32), String)> = self
+                    .widget_map
+                    .keys()
+                    .map(|id| {
+                        let id = id.to_string();
+                        (
+                            (self.config.get_pinned(&id), self.config.get_priority(&id)),
+                            id,
+                        )
+                    })
+                    .collect();
+                priorities.sort_by(|a, b| compare_order(&a.0, &b.0).then_with(|| a.1.cmp(&b.1)));
+                let lines: Vec<String> = priorities
+                    .into_iter()
+                    .map(|((pinned, priority), id)| match pinned {
+                        Some(_) => format!("{id}: {priority} (pinned)"),
+                        None => format!("{id}: {priority}"),
+                    })
+                    .collect();
+                ROk(RString::from(lines.join("\n")))
+            }
+            ["move", activity, index] => {
+                let Ok(index) = index.parse::<usize>() else {
+                    return RErr(RBoxError::from_fmt("the index should be a positive number"));
+                };
+                let Some(widget) = self
+                    .widget_map
+                    .iter()
+                    .find(|(id, _)| id.to_string() == *activity)
+                    .map(|(_, widget)| widget.clone().upcast::<gtk::Widget>())
+                else {
+                    return RErr(RBoxError::from_fmt(&format!(
+                        "activity not found: {activity}"
+                    )));
+                };
+                match self.move_in_container(&widget, index) {
+                    Some(index) => ROk(RString::from(format!("moved to {index}"))),
+                    None => RErr(RBoxError::from_fmt(
+                        "stacked activities are ordered by z_index",
+                    )),
+                }
+            }
+            ["keyboard", mode, window_name @ ..] => {
+                let Some(mode) = KeyboardMode::parse(mode) else {
+                    return RErr(RBoxError::from_fmt(
+                        "invalid keyboard mode, use none, exclusive or on-demand",
+                    ));
+                };
+                // the default window is called ""
+                let window_name = window_name.join(" ");
+                let Some((window, _)) = self.windows_containers.get(&window_name) else {
+                    return RErr(RBoxError::from_fmt(&format!(
+                        "window not found: {window_name}"
+                    )));
+                };
+                if !self
+                    .config
+                    .get_for_window(&window_name)
+                    .window_position
+                    .uses_layer_shell()
+                {
+                    return RErr(RBoxError::from_fmt("the window doesn't use layer-shell"));
+                }
+                window.set_keyboard_mode(mode.map_gtk());
+                ROk(RString::from("OK"))
+            }
+            _ => RErr(RBoxError::from_fmt(
+                "unknown command, available commands: groups, priorities, move <activity> <index>, keyboard <mode> [window]",
+            )),
+        }
+    }
 }
 
 impl FallbackLayout {
@@ -194,7 +306,10 @@ impl FallbackLayout {
             .flat_map(|c| c.downcast::<EventController>())
         {
             if let Some(name) = controller.name() {
-                if name == "press_gesture" || name == "focus_controller" {
+                if matches!(
+                    name.as_str(),
+                    "press_gesture" | "focus_controller" | "action_gesture" | "action_scroll"
+                ) {
                     controllers.push(controller);
                 }
             }
@@ -227,6 +342,45 @@ impl FallbackLayout {
         });
         widget.add_controller(press_gesture);
 
+        // user defined actions
+        if let Some(actions) = self.config.get_actions(&id.to_string()) {
+            let action_gesture = gtk::GestureClick::new();
+            action_gesture.set_name(Some("action_gesture"));
+            action_gesture.set_button(0);
+            let click_actions = actions.clone();
+            action_gesture.connect_released(move |gest, _, _, _| {
+                let command = match gest.current_button() {
+                    gdk::BUTTON_PRIMARY => &click_actions.click,
+                    gdk::BUTTON_SECONDARY => &click_actions.right_click,
+                    _ => &None,
+                };
+                if let Some(command) = command {
+                    run_action(command);
+                }
+            });
+            widget.add_controller(action_gesture);
+
+            let action_scroll =
+                gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL);
+            action_scroll.set_name(Some("action_scroll"));
+            let scroll_actions = actions.clone();
+            action_scroll.connect_scroll(move |_, _, dy| {
+                let command = if dy < 0.0 {
+                    &scroll_actions.scroll_up
+                } else {
+                    &scroll_actions.scroll_down
+                };
+                match command {
+                    Some(command) => {
+                        run_action(command);
+                        glib::Propagation::Stop
+                    }
+                    None => glib::Propagation::Proceed,
+                }
+            });
+            widget.add_controller(action_scroll);
+        }
+
         // auto minimize (to Compact mode) controller
         if config.auto_minimize_timeout >= 0 {
             let cancel_minimize = self.cancel_minimize.clone();
@@ -263,6 +417,25 @@ impl FallbackLayout {
         }
     }
 
+    /// Replaces the classes from the previous `css_classes` of each window with the current ones
+    fn update_window_classes(&mut self) {
+        for (window_name, (window, _)) in self.windows_containers.iter() {
+            let classes = self.config.get_for_window(window_name).css_classes;
+            let previous = self
+                .window_classes
+                .insert(window_name.clone(), classes.clone())
+                .unwrap_or_default();
+            for class in previous.iter().filter(|class| !classes.contains(class)) {
+                window.remove_css_class(class);
+            }
+            for class in classes.iter() {
+                window.add_css_class(class);
+            }
+        }
+        self.window_classes
+            .retain(|window_name, _| self.windows_containers.contains_key(window_name));
+    }
+
     fn configure_containers(&self) {
         for (window_name, (_, container)) in self.windows_containers.iter() {
             let config = self.config.get_for_window(window_name);
@@ -271,11 +444,22 @@ impl FallbackLayout {
             } else {
                 container.set_orientation(gtk::Orientation::Vertical);
             }
-            if !config.window_position.layer_shell {
+            if !config.window_position.uses_layer_shell() {
                 container.set_halign(config.window_position.h_anchor.map_gtk());
                 container.set_valign(config.window_position.v_anchor.map_gtk());
             }
-            container.set_spacing(0);
+            container.set_spacing(config.spacing.max(0));
+            let padding = config.padding.max(0);
+            container.set_margin_top(padding);
+            container.set_margin_bottom(padding);
+            container.set_margin_start(padding);
+            container.set_margin_end(padding);
+        }
+        for ((window_name, _), group_container) in self.group_containers.iter() {
+            if let Some((_, container)) = self.windows_containers.get(window_name) {
+                group_container.set_orientation(container.orientation());
+                group_container.set_spacing(container.spacing());
+            }
         }
     }
     // FIXME: this is terribly inefficient
@@ -288,15 +472,11 @@ impl FallbackLayout {
                 .windows_containers
                 .contains_key(&window_name.to_string())
             {
-                for child in window
-                    .child()
-                    .unwrap()
-                    .observe_children()
-                    .iter::<glib::Object>()
-                    .flatten()
-                {
-                    let widget = child.downcast::<gtk::Widget>().unwrap();
-                    if let Some((id, _)) = self.widget_map.iter().find(|(_, w)| *w == &widget) {
+                let container = window_box(&window);
+                for (id, widget) in self.widget_map.iter() {
+                    if container.is_some()
+                        && self.window_container_of(widget).as_ref() == container.as_ref()
+                    {
                         orphan_widgets.push(id.clone());
                     }
                 }
@@ -306,17 +486,13 @@ impl FallbackLayout {
         }
         // remove windows that are no longer in the config
         let mut windows_to_remove: Vec<String> = Vec::new();
-        for (window_name, (window, _)) in self.windows_containers.iter() {
+        for (window_name, (window, container)) in self.windows_containers.iter() {
             if !self.config.windows.contains_key(&window_name.to_string()) {
-                for child in window
-                    .child()
-                    .unwrap()
-                    .observe_children()
-                    .iter::<glib::Object>()
-                    .flatten()
-                {
-                    let widget = child.downcast::<gtk::Widget>().unwrap();
-                    if let Some((id, _)) = self.widget_map.iter().find(|(_, w)| *w == &widget) {
+                for (id, widget) in self.widget_map.iter() {
+                    if self
+                        .window_container_of(widget)
+                        .is_some_and(|parent| &parent == container)
+                    {
                         orphan_widgets.push(id.clone());
                     }
                 }
@@ -328,6 +504,9 @@ impl FallbackLayout {
             self.windows_containers.remove(&window_name);
             log::trace!("removing window no longer in config {}", window_name);
         }
+        let windows_containers = &self.windows_containers;
+        self.group_containers
+            .retain(|(window_name, _), _| windows_containers.contains_key(window_name));
         // create new windows
         let existing_windows: Vec<String> = self.windows_containers.keys().cloned().collect();
         let mut windows_to_create: Vec<String> = Vec::new();
@@ -347,7 +526,9 @@ impl FallbackLayout {
         }
         let mut to_update = Vec::new();
         for (id, widget) in self.widget_map.iter() {
-            let parent = widget.parent().unwrap().downcast::<gtk::Box>().unwrap();
+            let Some(parent) = self.window_container_of(widget) else {
+                continue;
+            };
             if let Some((current_window, (_, _))) = self
                 .windows_containers
                 .iter()
@@ -381,9 +562,13 @@ impl FallbackLayout {
         }
         let window = gtk::ApplicationWindow::new(&self.app);
         window.set_title(Some(window_name));
+        window.add_css_class("dynisland");
         let container = gtk::Box::new(gtk::Orientation::Horizontal, 5);
         container.add_css_class("activity-container");
-        window.set_child(Some(&container));
+        // the activities with a z_index are stacked over the container
+        let overlay = gtk::Overlay::new();
+        overlay.set_child(Some(&container));
+        window.set_child(Some(&overlay));
         self.config
             .get_for_window(window_name)
             .window_position
@@ -393,6 +578,7 @@ impl FallbackLayout {
         self.windows_containers
             .insert(window_name.to_string(), (window, container));
         self.configure_containers();
+        self.update_window_classes();
         log::trace!("created new window {}", window_name);
     }
 
@@ -402,15 +588,218 @@ impl FallbackLayout {
         widget: &ActivityWidget,
     ) {
         let window_name = self.get_window_name(activity_id);
-        let container = &self
+        let container = self
             .windows_containers
             .get(&window_name)
             .expect(&format!(
                 "there should be a default container for {}",
                 window_name
             ))
-            .1;
-        container.append(widget);
+            .1
+            .clone();
+        if self.config.get_z_index(&activity_id.to_string()) > 0 {
+            if let Some(overlay) = overlay_of(&container) {
+                overlay.add_overlay(widget);
+                self.sort_stacked(&overlay);
+                return;
+            }
+        }
+        match self.config.get_group(&activity_id.to_string()) {
+            Some(group) => {
+                let group_container = self.group_container(&window_name, &group, &container);
+                group_container.append(widget);
+                self.sort_by_order(&group_container);
+            }
+            None => container.append(widget),
+        }
+        self.sort_by_order(&container);
+    }
+
+    /// position in `pinned` and priority of an activity or a group
+    fn order_of(&self, child: &gtk::Widget) -> (Option<usize>, i32) {
+        if let Some(group) = self
+            .group_containers
+            .values()
+            .find(|group| **group == *child)
+        {
+            // a group goes where its most important activity would
+            let members: Vec<(Option<usize>, i32)> = group
+                .observe_children()
+                .iter::<glib::Object>()
+                .flatten()
+                .filter_map(|member| member.downcast::<gtk::Widget>().ok())
+                .map(|member| self.order_of(&member))
+                .collect();
+            return (
+                members.iter().filter_map(|(pinned, _)| *pinned).min(),
+                members
+                    .iter()
+                    .map(|(_, priority)| *priority)
+                    .max()
+                    .unwrap_or(0),
+            );
+        }
+        self.widget_map
+            .iter()
+            .find(|(_, widget)| **widget == *child)
+            .map(|(id, _)| {
+                let id = id.to_string();
+                (self.config.get_pinned(&id), self.config.get_priority(&id))
+            })
+            .unwrap_or((None, 0))
+    }
+
+    /// orders the children of a row or a group, pinned first in the order of `pinned`, then by priority,
+    /// keeping the order of the ones with the same priority
+    fn sort_by_order(&self, container: &gtk::Box) {
+        let mut children: Vec<((Option<usize>, i32), gtk::Widget)> = container
+            .observe_children()
+            .iter::<glib::Object>()
+            .flatten()
+            .filter_map(|child| child.downcast::<gtk::Widget>().ok())
+            .map(|child| (self.order_of(&child), child))
+            .collect();
+        children.sort_by(|a, b| compare_order(&a.0, &b.0));
+        let mut previous: Option<gtk::Widget> = None;
+        for (_, child) in children {
+            container.reorder_child_after(&child, previous.as_ref());
+            previous = Some(child);
+        }
+    }
+
+    /// moves a child of a row or a group to `index` among its siblings, returning the index it was moved to.
+    /// It's kept between the siblings with a different position in `pinned` or priority,
+    /// so the next sort keeps it there
+    fn move_in_container(&self, child: &gtk::Widget, index: usize) -> Option<usize> {
+        let container = child.parent()?.downcast::<gtk::Box>().ok()?;
+        let order = self.order_of(child);
+        let siblings: Vec<gtk::Widget> = container
+            .observe_children()
+            .iter::<glib::Object>()
+            .flatten()
+            .filter_map(|sibling| sibling.downcast::<gtk::Widget>().ok())
+            .filter(|sibling| sibling != child)
+            .collect();
+        let first = siblings
+            .iter()
+            .filter(|sibling| compare_order(&self.order_of(sibling), &order).is_lt())
+            .count();
+        let last = first
+            + siblings
+                .iter()
+                .filter(|sibling| compare_order(&self.order_of(sibling), &order).is_eq())
+                .count();
+        let index = index.clamp(first, last);
+        let previous = index.checked_sub(1).map(|previous| &siblings[previous]);
+        container.reorder_child_after(child, previous);
+        Some(index)
+    }
+
+    fn group_container(
+        &mut self,
+        window_name: &str,
+        group: &str,
+        container: &gtk::Box,
+    ) -> gtk::Box {
+        self.group_containers
+            .entry((window_name.to_string(), group.to_string()))
+            .or_insert_with(|| {
+                let group_container = gtk::Box::new(container.orientation(), container.spacing());
+                group_container.add_css_class("activity-group");
+                group_container.add_css_class(group);
+                container.append(&group_container);
+                log::trace!("created group {group} in window {window_name}");
+                group_container
+            })
+            .clone()
+    }
+
+    /// re-adds the stacked activities of a window sorted by z_index, the last one added is on top
+    fn sort_stacked(&self, overlay: &gtk::Overlay) {
+        let main_child = overlay.child();
+        let mut stacked: Vec<(i32, gtk::Widget)> = overlay
+            .observe_children()
+            .iter::<glib::Object>()
+            .flatten()
+            .filter_map(|child| child.downcast::<gtk::Widget>().ok())
+            .filter(|child| Some(child) != main_child.as_ref())
+            .map(|child| {
+                let z_index = self
+                    .widget_map
+                    .iter()
+                    .find(|(_, widget)| **widget == child)
+                    .map(|(id, _)| self.config.get_z_index(&id.to_string()))
+                    .unwrap_or(0);
+                (z_index, child)
+            })
+            .collect();
+        stacked.sort_by_key(|(z_index, _)| *z_index);
+        for (_, child) in stacked.iter() {
+            overlay.remove_overlay(child);
+        }
+        for (_, child) in stacked.iter() {
+            overlay.add_overlay(child);
+            // the window has to be big enough for the stacked activities too
+            overlay.set_measure_overlay(child, true);
+        }
+    }
+
+    /// returns the container of the window the widget is in, skipping its group container
+    fn window_container_of(&self, widget: &ActivityWidget) -> Option<gtk::Box> {
+        let parent = widget.parent()?;
+        if let Some(overlay) = parent.downcast_ref::<gtk::Overlay>() {
+            return overlay.child()?.downcast::<gtk::Box>().ok();
+        }
+        let parent = parent.downcast::<gtk::Box>().ok()?;
+        if self.group_containers.values().any(|group| group == &parent) {
+            parent.parent()?.downcast::<gtk::Box>().ok()
+        } else {
+            Some(parent)
+        }
+    }
+
+    /// moves the widgets whose group or z_index changed in the config
+    fn update_groups(&mut self) {
+        let mut to_update = Vec::new();
+        for (id, widget) in self.widget_map.iter() {
+            let is_stacked = widget
+                .parent()
+                .is_some_and(|parent| parent.is::<gtk::Overlay>());
+            let should_stack = self.config.get_z_index(&id.to_string()) > 0;
+            let current_group = widget.parent().and_then(|parent| {
+                self.group_containers
+                    .iter()
+                    .find(|(_, group)| parent == **group)
+                    .map(|((_, group), _)| group.clone())
+            });
+            let group = if should_stack {
+                None
+            } else {
+                self.config.get_group(&id.to_string())
+            };
+            if is_stacked != should_stack || current_group != group {
+                to_update.push(id.clone());
+            }
+        }
+        for id in to_update {
+            let widget = self.widget_map.get(&id).unwrap().clone();
+            if self
+                .remove_activity_from_container(&id, widget.clone())
+                .is_ok()
+            {
+                self.add_activity_to_container(&id, &widget);
+                log::trace!("moving widget {} to its group", id);
+            }
+        }
+        for group_container in self.group_containers.values() {
+            self.sort_by_order(group_container);
+        }
+        for (_, container) in self.windows_containers.values() {
+            self.sort_by_order(container);
+            if let Some(overlay) = overlay_of(container) {
+                self.sort_stacked(&overlay);
+            }
+        }
     }
 
     fn remove_activity_from_container(
@@ -418,7 +807,18 @@ impl FallbackLayout {
         activity: &ActivityIdentifier,
         widget: ActivityWidget,
     ) -> Result<()> {
-        let widget_container = match widget.parent().unwrap().downcast::<gtk::Box>() {
+        let parent = widget.parent().unwrap();
+        if let Some(overlay) = parent.downcast_ref::<gtk::Overlay>() {
+            overlay.remove_overlay(&widget);
+            let Some(container) = overlay
+                .child()
+                .and_then(|child| child.downcast::<gtk::Box>().ok())
+            else {
+                return Ok(());
+            };
+            return self.close_if_empty(&container);
+        }
+        let widget_container = match parent.downcast::<gtk::Box>() {
             Ok(parent) => parent,
             Err(_) => {
                 log::warn!(
@@ -431,13 +831,42 @@ impl FallbackLayout {
                 );
             }
         };
+        widget_container.remove(&widget);
+        let group = self
+            .group_containers
+            .iter()
+            .find(|(_, group)| *group == &widget_container)
+            .map(|(key, _)| key.clone());
+        let widget_container = match group {
+            Some(group) => {
+                let Some(container) = widget_container
+                    .parent()
+                    .and_then(|parent| parent.downcast::<gtk::Box>().ok())
+                else {
+                    return Ok(());
+                };
+                if widget_container.first_child().is_none() {
+                    container.remove(&widget_container);
+                    self.group_containers.remove(&group);
+                    log::trace!("removing empty group {}", group.1);
+                }
+                container
+            }
+            None => widget_container,
+        };
+        self.close_if_empty(&widget_container)
+    }
+
+    /// closes the window of the container and creates it again if it has no activities
+    fn close_if_empty(&mut self, widget_container: &gtk::Box) -> Result<()> {
         let name = if let Some((name, (window, container))) = self
             .windows_containers
             .iter()
-            .find(move |(_, (_, container))| &widget_container == container)
+            .find(move |(_, (_, container))| widget_container == container)
         {
-            container.remove(&widget);
-            if container.first_child().is_some() {
+            let has_stacked = overlay_of(container)
+                .is_some_and(|overlay| overlay.observe_children().n_items() > 1);
+            if container.first_child().is_some() || has_stacked {
                 return Ok(());
             }
             window.close();
@@ -451,3 +880,108 @@ impl FallbackLayout {
         Ok(())
     }
 }
+
+fn overlay_of(container: &gtk::Box) -> Option<gtk::Overlay> {
+    container.parent()?.downcast::<gtk::Overlay>().ok()
+}
+
+/// returns the container of a window created by this layout
+fn window_box(window: &gtk::Window) -> Option<gtk::Box> {
+    let overlay = window.child()?.downcast::<gtk::Overlay>().ok()?;
+    overlay.child()?.downcast::<gtk::Box>().ok()
+}
+
+/// pinned first by their position in `pinned`, then higher priority first
+fn compare_order(a: &(Option<usize>, i32), b: &(Option<usize>, i32)) -> std::cmp::Ordering {
+    match (a.0, b.0) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => b.1.cmp(&a.1),
+    }
+}
+
+/// replaces the characters that can't be used in a css class name
+fn css_class_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn run_action(command: &str) {
+    let command = command.to_string();
+    std::thread::spawn(move || {
+        match std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+        {
+            Ok(status) if !status.success() => {
+                log::warn!("action `{command}` exited with {status}")
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("failed to run action `{command}`: {err}"),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::*;
+
+    #[test]
+    fn pinned_come_first_in_their_order() {
+        // the priority doesn't matter once an activity is pinned
+        assert_eq!(
+            compare_order(&(Some(0), -5), &(Some(1), 10)),
+            Ordering::Less
+        );
+        assert_eq!(compare_order(&(Some(1), 0), &(None, 100)), Ordering::Less);
+        assert_eq!(
+            compare_order(&(None, 100), &(Some(1), 0)),
+            Ordering::Greater
+        );
+        // two activities matching the same pinned pattern tie
+        assert_eq!(compare_order(&(Some(2), 1), &(Some(2), 3)), Ordering::Equal);
+    }
+
+    #[test]
+    fn higher_priority_comes_first() {
+        assert_eq!(compare_order(&(None, 2), &(None, 1)), Ordering::Less);
+        assert_eq!(compare_order(&(None, -1), &(None, 0)), Ordering::Greater);
+        assert_eq!(compare_order(&(None, 0), &(None, 0)), Ordering::Equal);
+    }
+
+    #[test]
+    fn ties_keep_the_insertion_order() {
+        let mut children = vec![
+            ((None, 0), "first"),
+            ((Some(1), 0), "pinned-second"),
+            ((None, 5), "important"),
+            ((None, 0), "second"),
+            ((Some(0), 0), "pinned-first"),
+            ((None, 0), "third"),
+        ];
+        children.sort_by(|a, b| compare_order(&a.0, &b.0));
+        let names: Vec<&str> = children.iter().map(|(_, name)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                "pinned-first",
+                "pinned-second",
+                "important",
+                "first",
+                "second",
+                "third"
+            ]
+        );
+    }
+}
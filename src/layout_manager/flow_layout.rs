@@ -0,0 +1,363 @@
+use std::{collections::HashMap, time::Duration};
+
+use abi_stable::{
+    sabi_extern_fn,
+    sabi_trait::TD_CanDowncast,
+    std_types::{
+        RBoxError, ROption,
+        RResult::{self, RErr, ROk},
+        RString, RVec,
+    },
+};
+use dynisland_core::{
+    abi::{
+        abi_stable, glib, gtk, gtk_layer_shell,
+        layout::{LayoutManagerType, SabiLayoutManager, SabiLayoutManager_TO},
+        log,
+        module::ActivityIdentifier,
+        SabiApplication, SabiWidget,
+    },
+    graphics::activity_widget::{boxed_activity_mode::ActivityMode, ActivityWidget},
+    ron,
+};
+use gtk::{prelude::*, ApplicationWindow, StateFlags};
+use gtk_layer_shell::LayerShell;
+use ron::ser::PrettyConfig;
+
+use crate::layout_manager::{config::FlowLayoutConfig, window_position::KeyboardMode};
+
+pub const NAME: &str = "FlowLayout";
+
+/// Puts all the activities in a single window, wrapping them on more rows when they don't fit
+pub struct FlowLayout {
+    app: gtk::Application,
+    window: Option<(ApplicationWindow, gtk::FlowBox)>,
+    widget_map: HashMap<ActivityIdentifier, ActivityWidget>,
+    config: FlowLayoutConfig,
+}
+
+#[sabi_extern_fn]
+pub extern "C" fn new(app: SabiApplication) -> RResult<LayoutManagerType, RBoxError> {
+    let app = app.try_into().unwrap();
+    let this = FlowLayout {
+        app,
+        window: None,
+        widget_map: HashMap::new(),
+        config: FlowLayoutConfig::default(),
+    };
+    ROk(SabiLayoutManager_TO::from_value(this, TD_CanDowncast))
+}
+
+impl SabiLayoutManager for FlowLayout {
+    fn init(&mut self) {
+        let window = gtk::ApplicationWindow::new(&self.app);
+        window.set_title(Some(""));
+        window.add_css_class("dynisland");
+        let container = new_container();
+        window.set_child(Some(&container));
+        self.config
+            .window_position
+            .init_window(&window.clone().upcast());
+        window.present();
+        self.window = Some((window, container));
+        self.configure_container();
+    }
+
+    fn update_config(&mut self, config: RString) -> RResult<(), RBoxError> {
+        match serde_json::from_str::<FlowLayoutConfig>(&config) {
+            Ok(conf) => {
+                self.config = conf;
+            }
+            Err(err) => {
+                log::warn!(
+                    "Failed to parse config into struct, using default: {:#?}",
+                    err
+                );
+            }
+        }
+        log::trace!("current config: {:#?}", self.config);
+        if let Some((window, _)) = &self.window {
+            self.config
+                .window_position
+                .reconfigure_window(&window.clone().upcast());
+        }
+        self.configure_container();
+        for widget in self.widget_map.values() {
+            self.configure_widget(widget);
+        }
+        ROk(())
+    }
+
+    fn default_config(&self) -> RResult<RString, RBoxError> {
+        match ron::ser::to_string_pretty(&FlowLayoutConfig::default(), PrettyConfig::default()) {
+            Ok(conf) => ROk(RString::from(conf)),
+            Err(err) => RErr(RBoxError::new(err)),
+        }
+    }
+
+    fn add_activity(&mut self, activity_id: &ActivityIdentifier, widget: SabiWidget) {
+        let widget: gtk::Widget = widget.try_into().unwrap();
+        let widget = match widget.downcast::<ActivityWidget>() {
+            Ok(widget) => widget,
+            Err(_) => {
+                log::error!("widget {} is not an ActivityWidget", activity_id);
+                return;
+            }
+        };
+        let Some((_, container)) = &self.window else {
+            log::error!("{NAME} was not initialized, can't add {activity_id}");
+            return;
+        };
+        self.configure_widget(&widget);
+        container.append(&widget);
+        self.widget_map.insert(activity_id.clone(), widget);
+    }
+
+    fn get_activity(&self, activity: &ActivityIdentifier) -> ROption<SabiWidget> {
+        self.widget_map
+            .get(activity)
+            .map(|wid| SabiWidget::from(wid.clone().upcast::<gtk::Widget>()))
+            .into()
+    }
+
+    fn remove_activity(&mut self, activity: &ActivityIdentifier) {
+        let Some(widget) = self.widget_map.remove(activity) else {
+            return;
+        };
+        if let Some((_, container)) = &self.window {
+            // also removes the FlowBoxChild the widget was wrapped in
+            container.remove(&widget);
+        }
+    }
+
+    fn list_activities(&self) -> RVec<ActivityIdentifier> {
+        self.widget_map.keys().cloned().collect()
+    }
+
+    fn list_windows(&self) -> RVec<RString> {
+        self.window.iter().map(|_| RString::from("")).collect()
+    }
+
+    fn activity_notification(
+        &self,
+        activity: &ActivityIdentifier,
+        mode_id: u8,
+        duration: ROption<u64>,
+    ) {
+        let Some(widget) = self.widget_map.get(activity) else {
+            return;
+        };
+        let mode = ActivityMode::try_from(mode_id).unwrap();
+        widget.set_mode(mode);
+        if matches!(mode, ActivityMode::Minimal | ActivityMode::Compact)
+            || self.config.auto_minimize_timeout < 0
+        {
+            return;
+        }
+        let timeout = duration.unwrap_or(self.config.auto_minimize_timeout as u64);
+        let widget = widget.clone();
+        glib::timeout_add_local_once(Duration::from_millis(timeout), move || {
+            if !widget.state_flags().contains(StateFlags::PRELIGHT) && widget.mode() == mode {
+                //mouse is not on widget and mode hasn't changed
+                widget.set_mode(ActivityMode::Compact);
+            }
+        });
+    }
+
+    fn cli_command(&self, args: RString) -> RResult<RString, RBoxError> {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        match args[..] {
+            ["move", activity, index] => {
+                let Ok(index) = index.parse::<usize>() else {
+                    return RErr(RBoxError::from_fmt("the index should be a positive number"));
+                };
+                let Some(widget) = self
+                    .widget_map
+                    .iter()
+                    .find(|(id, _)| id.to_string() == activity)
+                    .map(|(_, widget)| widget)
+                else {
+                    return RErr(RBoxError::from_fmt(&format!(
+                        "activity not found: {activity}"
+                    )));
+                };
+                match self.move_in_container(widget, index) {
+                    Some(index) => ROk(RString::from(format!("moved to {index}"))),
+                    None => RErr(RBoxError::from_fmt(&format!("{NAME} was not initialized"))),
+                }
+            }
+            ["keyboard", mode] => {
+                let Some(mode) = KeyboardMode::parse(mode) else {
+                    return RErr(RBoxError::from_fmt(
+                        "invalid keyboard mode, use none, exclusive or on-demand",
+                    ));
+                };
+                let Some((window, _)) = &self.window else {
+                    return RErr(RBoxError::from_fmt(&format!("{NAME} was not initialized")));
+                };
+                if !self.config.window_position.uses_layer_shell() {
+                    return RErr(RBoxError::from_fmt("the window doesn't use layer-shell"));
+                }
+                window.set_keyboard_mode(mode.map_gtk());
+                ROk(RString::from("OK"))
+            }
+            _ => RErr(RBoxError::from_fmt(
+                "unknown command, available commands: move <activity> <index>, keyboard <mode>",
+            )),
+        }
+    }
+}
+
+fn new_container() -> gtk::FlowBox {
+    let container = gtk::FlowBox::new();
+    container.add_css_class("activity-container");
+    container.set_selection_mode(gtk::SelectionMode::None);
+    container.set_homogeneous(false);
+    container
+}
+
+impl FlowLayout {
+    fn configure_container(&self) {
+        let Some((_, container)) = &self.window else {
+            return;
+        };
+        let config = &self.config;
+        container.set_max_children_per_line(config.max_children_per_line.max(1));
+        container.set_min_children_per_line(
+            config
+                .min_children_per_line
+                .clamp(1, config.max_children_per_line.max(1)),
+        );
+        container.set_row_spacing(config.row_spacing);
+        container.set_column_spacing(config.column_spacing);
+        if !config.window_position.uses_layer_shell() {
+            container.set_halign(config.window_position.h_anchor.map_gtk());
+            container.set_valign(config.window_position.v_anchor.map_gtk());
+        }
+    }
+
+    /// Moves the widget to `index` among the activities, clamped to the last position.
+    /// Returns the index it ended up at
+    fn move_in_container(&self, widget: &ActivityWidget, index: usize) -> Option<usize> {
+        let (_, container) = self.window.as_ref()?;
+        let index = index.min(self.widget_map.len().saturating_sub(1));
+        // the FlowBox can't reorder its children, the FlowBoxChild is replaced
+        container.remove(widget);
+        container.insert(widget, index as i32);
+        Some(index)
+    }
+
+    fn configure_widget(&self, widget: &ActivityWidget) {
+        widget.set_valign(self.config.window_position.v_anchor.map_gtk());
+        widget.set_halign(self.config.window_position.h_anchor.map_gtk());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use gtk::gio;
+
+    use super::*;
+    use crate::test_util::with_gtk;
+
+    /// A layout with `count` activities, without presenting its window
+    fn test_layout(count: usize) -> (FlowLayout, Vec<ActivityWidget>) {
+        // without an id the application doesn't use the session bus
+        let app = gtk::Application::new(None, gio::ApplicationFlags::NON_UNIQUE);
+        app.register(None::<&gio::Cancellable>).unwrap();
+        let window = ApplicationWindow::new(&app);
+        let container = new_container();
+        window.set_child(Some(&container));
+        let mut layout = FlowLayout {
+            app,
+            window: Some((window, container)),
+            widget_map: HashMap::new(),
+            config: FlowLayoutConfig::default(),
+        };
+        layout.configure_container();
+        let widgets = (0..count)
+            .map(|i| {
+                let widget = glib::Object::new::<ActivityWidget>();
+                widget.set_size_request(100, 40);
+                layout.add_activity(
+                    &ActivityIdentifier::new("test-module", &format!("activity-{i}")),
+                    widget.clone().upcast::<gtk::Widget>().into(),
+                );
+                widget
+            })
+            .collect();
+        (layout, widgets)
+    }
+
+    /// The index of the FlowBoxChild each widget is wrapped in
+    fn positions(widgets: &[ActivityWidget]) -> Vec<i32> {
+        widgets
+            .iter()
+            .map(|widget| {
+                widget
+                    .parent()
+                    .and_downcast::<gtk::FlowBoxChild>()
+                    .unwrap()
+                    .index()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn activities_wrap_when_the_width_is_too_narrow() {
+        with_gtk(|| {
+            let (layout, widgets) = test_layout(6);
+            assert_eq!(layout.list_activities().len(), 6);
+            let (window, container) = layout.window.clone().unwrap();
+
+            let (min_width, _, _, _) = widgets[0].measure(gtk::Orientation::Horizontal, -1);
+            // room for two activities in a row
+            let width = min_width * 2 + min_width / 2;
+            let (_, height, _, _) = container.measure(gtk::Orientation::Vertical, width);
+            container.allocate(width, height, -1, None);
+            let rows: BTreeSet<i32> = widgets
+                .iter()
+                .map(|widget| widget.compute_bounds(&container).unwrap().y() as i32)
+                .collect();
+            assert!(rows.len() > 1, "the activities are on {} row", rows.len());
+            window.destroy();
+        });
+    }
+
+    #[test]
+    fn activities_are_moved() {
+        with_gtk(|| {
+            let (layout, widgets) = test_layout(3);
+            assert_eq!(positions(&widgets), [0, 1, 2]);
+            let moved = layout.cli_command("move activity-2@test-module 0".into());
+            assert_eq!(moved.unwrap().as_str(), "moved to 0");
+            assert_eq!(positions(&widgets), [1, 2, 0]);
+            // past the end it's moved to the last position
+            let moved = layout.cli_command("move activity-2@test-module 10".into());
+            assert_eq!(moved.unwrap().as_str(), "moved to 2");
+            assert_eq!(positions(&widgets), [0, 1, 2]);
+            assert!(layout
+                .cli_command("move missing@test-module 0".into())
+                .is_err());
+            assert!(layout
+                .cli_command("move activity-0@test-module -1".into())
+                .is_err());
+            layout.window.unwrap().0.destroy();
+        });
+    }
+
+    #[test]
+    fn keyboard_mode_needs_layer_shell() {
+        with_gtk(|| {
+            let (mut layout, _) = test_layout(0);
+            assert!(layout.cli_command("keyboard sometimes".into()).is_err());
+            // the test window isn't a layer-shell surface
+            layout.config.window_position.layer_shell = false;
+            let err = layout.cli_command("keyboard on-demand".into()).unwrap_err();
+            assert_eq!(err.to_string(), "the window doesn't use layer-shell");
+            layout.window.unwrap().0.destroy();
+        });
+    }
+}
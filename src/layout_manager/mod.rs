@@ -1,4 +1,5 @@
 mod config;
 pub mod fallback_layout;
-mod window_position;
+pub mod flow_layout;
+pub(crate) mod window_position;
 pub const NAME: &str = "FallbackLayout";
@@ -1,4 +1,9 @@
 use dynisland_core::abi::{gdk, gtk, gtk_layer_shell, log};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    OnceLock,
+};
+
 use gdk::prelude::*;
 use gtk::{prelude::*, Window};
 use gtk_layer_shell::LayerShell;
@@ -50,6 +55,38 @@ impl Layer {
     }
 }
 
+/// `OnDemand` gives the window focus when it's clicked,
+/// it needs a compositor that supports layer-shell v4 or later, otherwise it behaves like `None`
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "KeyboardMode")]
+pub enum KeyboardMode {
+    #[default]
+    #[serde(alias = "none")]
+    None,
+    #[serde(alias = "exclusive")]
+    Exclusive,
+    #[serde(alias = "on_demand")]
+    OnDemand,
+}
+
+impl KeyboardMode {
+    pub fn map_gtk(&self) -> gtk_layer_shell::KeyboardMode {
+        match self {
+            KeyboardMode::None => gtk_layer_shell::KeyboardMode::None,
+            KeyboardMode::Exclusive => gtk_layer_shell::KeyboardMode::Exclusive,
+            KeyboardMode::OnDemand => gtk_layer_shell::KeyboardMode::OnDemand,
+        }
+    }
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "none" => Some(KeyboardMode::None),
+            "exclusive" => Some(KeyboardMode::Exclusive),
+            "on_demand" | "on-demand" => Some(KeyboardMode::OnDemand),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct WindowPosition {
@@ -60,7 +97,9 @@ pub struct WindowPosition {
     pub(crate) margin_y: i32,
     pub(crate) exclusive_zone: i32,
     pub(crate) monitor: String,
+    /// false, or a compositor without layer-shell (X11, gnome), gives a normal resizable window
     pub(crate) layer_shell: bool,
+    pub(crate) keyboard_mode: KeyboardMode,
 }
 
 impl Default for WindowPosition {
@@ -74,6 +113,7 @@ impl Default for WindowPosition {
             exclusive_zone: -1,
             monitor: String::from(""),
             layer_shell: true,
+            keyboard_mode: KeyboardMode::None,
         }
     }
 }
@@ -89,11 +129,41 @@ pub struct DeWindowPosition {
     pub(crate) exclusive_zone: Option<i32>,
     pub(crate) monitor: Option<String>,
     pub(crate) layer_shell: Option<bool>,
+    pub(crate) keyboard_mode: Option<KeyboardMode>,
+}
+
+/// Whether the compositor supports layer-shell, checked once and logged the first time
+pub fn layer_shell_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        let supported = gtk_layer_shell::is_supported();
+        if !supported {
+            log::warn!("the compositor doesn't support layer-shell, using normal windows");
+        }
+        supported
+    })
+}
+
+/// `keep_above` from the general config, it replaces the layer of every window
+static KEEP_ABOVE: AtomicBool = AtomicBool::new(false);
+
+/// Puts the windows configured after this on the `Overlay` layer, above fullscreen windows
+pub fn set_keep_above(keep_above: bool) {
+    KEEP_ABOVE.store(keep_above, Ordering::Relaxed);
 }
 
 impl WindowPosition {
+    /// `layer_shell`, if the compositor supports it
+    pub fn uses_layer_shell(&self) -> bool {
+        self.layer_shell && layer_shell_supported()
+    }
+
     pub fn config_layer_shell_for(&self, window: &Window) {
-        window.set_layer(self.layer.map_gtk());
+        if KEEP_ABOVE.load(Ordering::Relaxed) {
+            window.set_layer(gtk_layer_shell::Layer::Overlay);
+        } else {
+            window.set_layer(self.layer.map_gtk());
+        }
         match self.v_anchor {
             Alignment::Start => {
                 window.set_anchor(gtk_layer_shell::Edge::Top, true);
@@ -147,22 +217,29 @@ impl WindowPosition {
             window.set_monitor(&monitor);
         }
         window.set_namespace("dynisland");
+        window.set_keyboard_mode(self.keyboard_mode.map_gtk());
         window.set_exclusive_zone(self.exclusive_zone);
         window.set_resizable(false);
         window.queue_resize();
     }
 
     pub fn init_window(&self, window: &Window) {
-        if self.layer_shell {
+        if self.uses_layer_shell() {
             window.init_layer_shell();
             self.config_layer_shell_for(window.upcast_ref());
             window.connect_destroy(|_| log::debug!("LayerShell window was destroyed"));
         } else {
-            window.connect_destroy(|_| std::process::exit(0));
+            log::debug!("using a normal window");
+            window.set_resizable(true);
+            window.connect_destroy(|window| {
+                if let Some(app) = window.application() {
+                    app.quit();
+                }
+            });
         }
     }
     pub fn reconfigure_window(&self, window: &Window) {
-        if self.layer_shell {
+        if self.uses_layer_shell() {
             if !window.is_layer_window() {
                 window.init_layer_shell();
             }
@@ -7,6 +7,9 @@
 pub mod app;
 pub mod cli;
 pub mod config;
+pub mod dbus;
 pub mod ipc;
 pub mod layout_manager;
 pub mod module_loading;
+#[cfg(test)]
+mod test_util;
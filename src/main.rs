@@ -7,14 +7,16 @@ use std::{
 };
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use dynisland::{
-    app::App,
+    app::{self, App, OnceMode},
     cli::{
         Cli,
         SubCommands::{self, *},
     },
     config, ipc,
+    layout_manager::{self, flow_layout},
+    module_loading,
 };
 use dynisland_core::abi::{abi_stable, log, module::UIServerCommand};
 use env_logger::Env;
@@ -32,6 +34,8 @@ use nix::unistd::Pid;
 // maybe it's in ScrollingLabel
 
 fn main() -> Result<()> {
+    // set_var isn't thread safe, this runs before gtk and tokio start their threads
+    std::env::set_var(config::DATA_DIR_ENV, config::get_data_dir());
     env_logger::Builder::new()
         // .filter_module("dynisland", log::LevelFilter::Debug)
         // .filter_module("dynisland_core", log::LevelFilter::Debug)
@@ -40,18 +44,49 @@ fn main() -> Result<()> {
         .parse_env(Env::default().default_filter_or(Level::Info.as_str()))
         .init();
 
-    let cli = Cli::parse();
+    let build_info: &'static str = Box::leak(build_info().into_boxed_str());
+    let mut cli = Cli::from_arg_matches(&Cli::command().long_version(build_info).get_matches())?;
     let config_dir = cli
         .config_path
         .clone()
         .unwrap_or(config::get_default_config_path());
-    let config = config::get_config(&config_dir);
+    config::set_ron_extensions(&cli.ron_extensions)?;
+    if cli.config_stdin {
+        config::read_config_from_stdin().context("failed to read the config from stdin")?;
+    }
+    let (config, errors) = config::load_config(&config_dir, cli.profile.as_deref());
     log::debug!("{cli:?}");
-    match cli.command {
-        Daemon { no_daemonize } => {
+    if log::log_enabled!(Level::Debug) {
+        for line in config::config_sources(&config_dir, &config, cli.profile.as_deref()) {
+            log::debug!("config source: {line}");
+        }
+    }
+    // these don't start gtk, so scripts can use them
+    if cli.print_config_path {
+        println!("{}", config_dir.display());
+    }
+    if cli.print_state_path {
+        println!("{}", config.get_runtime_dir().display());
+    }
+    if cli.print_config_path || cli.print_state_path {
+        return Ok(());
+    }
+    let Some(command) = cli.command.take() else {
+        Cli::command().print_help()?;
+        return Ok(());
+    };
+    match command {
+        Daemon {
+            no_daemonize,
+            inspector,
+            safe,
+            once,
+            once_delay,
+            screenshot,
+        } => {
             let runtime_dir = config.get_runtime_dir();
             if let Ok(stream) = UnixStream::connect(runtime_dir.join("dynisland.sock")) {
-                match ipc::send_recv_message(stream, &HealthCheck) {
+                match ipc::send_recv_message(stream, &HealthCheck, &config.general_style_config) {
                     Ok(_) => {
                         //app is already runnig
                         log::error!("Application is already running");
@@ -64,7 +99,11 @@ fn main() -> Result<()> {
             } else {
                 let _ = std::fs::remove_file(runtime_dir.join("dynisland.sock"));
             }
-            let pid = if !no_daemonize {
+            let once = (once || screenshot.is_some()).then(|| OnceMode {
+                delay: Duration::from_millis(once_delay),
+                screenshot,
+            });
+            let pid = if !no_daemonize && once.is_none() {
                 let log_path = runtime_dir.join("dynisland.log");
                 detach(&log_path)?
             } else {
@@ -72,11 +111,34 @@ fn main() -> Result<()> {
             };
             //init GTK
             gtk::init().with_context(|| "failed to init gtk")?;
-            let app = App::default();
+            let app = App {
+                open_inspector: inspector,
+                safe_mode: safe,
+                profile: cli.profile.clone(),
+                once,
+                ..Default::default()
+            };
             log::info!("pid: {pid}");
             app.run(&config_dir)?;
         }
-        Reload
+        Dev { module_name } => {
+            let runtime_dir = config.get_runtime_dir();
+            // the cli commands reach the dev instance through the same socket
+            if UnixStream::connect(runtime_dir.join("dynisland.sock")).is_ok() {
+                log::error!("Application is already running, stop it with `dynisland kill` first");
+                return Ok(());
+            }
+            let _ = std::fs::remove_file(runtime_dir.join("dynisland.sock"));
+            gtk::init().with_context(|| "failed to init gtk")?;
+            let app = App {
+                open_inspector: true,
+                profile: cli.profile.clone(),
+                dev_module: Some(module_name),
+                ..Default::default()
+            };
+            app.run(&config_dir)?;
+        }
+        Reload { force: _ }
         | Inspector
         | HealthCheck
         | ActivityNotification {
@@ -89,12 +151,32 @@ fn main() -> Result<()> {
             args: _,
         }
         | Layout { args: _ }
-        | ListActivities => {
+        | SetActivityVisible {
+            activity_identifier: _,
+            visible: _,
+        }
+        | SetActivityClasses {
+            activity_identifier: _,
+            classes: _,
+        }
+        | GetGeneral { field: _ }
+        | SetGeneral { field: _, value: _ }
+        | SetLayoutProfile { profile: _ }
+        | FilterActivities { pattern: _ }
+        | ClearFilter
+        | ListActivities { json: _ }
+        | Stats
+        | DumpCss => {
             let socket_path = config.get_runtime_dir().join("dynisland.sock");
             match UnixStream::connect(socket_path.clone()) {
                 Ok(stream) => {
-                    if let Some(response) = ipc::send_recv_message(stream, &cli.command)? {
+                    if let Some(response) =
+                        ipc::send_recv_message(stream, &command, &config.general_style_config)?
+                    {
                         println!("Response: \n{response}");
+                        if matches!(command, Reload { .. }) && response.starts_with("Error:") {
+                            anyhow::bail!("reload failed");
+                        }
                     }
                     // if cli.command == HealthCheck {
                     //     println!("OK");
@@ -113,7 +195,8 @@ fn main() -> Result<()> {
             let socket_path = config.get_runtime_dir().join("dynisland.sock");
             match UnixStream::connect(socket_path.clone()) {
                 Ok(stream) => {
-                    let response = ipc::send_recv_message(stream, &cli.command)?;
+                    let response =
+                        ipc::send_recv_message(stream, &command, &config.general_style_config)?;
                     println!("Kill message sent");
                     let has_responded = if let Some(response) = response {
                         println!("Response: \n{response}");
@@ -148,11 +231,19 @@ fn main() -> Result<()> {
                 }
             };
         }
-        Restart { no_daemonize } => {
+        Restart {
+            no_daemonize,
+            inspector,
+            safe,
+        } => {
             let socket_path = config.get_runtime_dir().join("dynisland.sock");
             match UnixStream::connect(socket_path.clone()) {
                 Ok(stream) => {
-                    let response = ipc::send_recv_message(stream, &SubCommands::Kill)?;
+                    let response = ipc::send_recv_message(
+                        stream,
+                        &SubCommands::Kill,
+                        &config.general_style_config,
+                    )?;
                     let has_responded = if let Some(response) = response {
                         log::info!("Response: \n{response}");
                         true
@@ -192,7 +283,12 @@ fn main() -> Result<()> {
             };
             //init GTK
             gtk::init().with_context(|| "failed to init gtk")?;
-            let app = App::default();
+            let app = App {
+                open_inspector: inspector,
+                safe_mode: safe,
+                profile: cli.profile.clone(),
+                ..Default::default()
+            };
             log::info!("pid: {pid}");
             app.run(&config_dir)?;
         }
@@ -213,10 +309,68 @@ fn main() -> Result<()> {
                 todo!();
             }
         }
+        DefaultCss => {
+            println!("{}", app::DEFAULT_SCSS);
+        }
+        Check => {
+            for line in config::config_sources(&config_dir, &config, cli.profile.as_deref()) {
+                println!("{line}");
+            }
+            if errors.is_empty() {
+                println!("no errors");
+            } else {
+                for error in errors.iter() {
+                    println!("error: {error}");
+                }
+                std::process::exit(1);
+            }
+        }
+        Schema => {
+            let module_names: Vec<String> = module_loading::get_module_definitions(&config_dir)
+                .into_keys()
+                .collect();
+            let mut layout_names: Vec<String> = module_loading::get_lm_definitions(&config_dir)
+                .into_keys()
+                .collect();
+            layout_names.push(layout_manager::NAME.to_string());
+            layout_names.push(flow_layout::NAME.to_string());
+            let schema = config::json_schema(&module_names, &layout_names);
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+        }
     }
     Ok(())
 }
 
+/// Shown by `--version`, so bug reports say how the binary was built
+fn build_info() -> String {
+    let features: Vec<&str> = [
+        ("embed_modules", cfg!(feature = "embed_modules")),
+        ("pkl", cfg!(feature = "pkl")),
+        ("completions", cfg!(feature = "completions")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect();
+    let (modules, mut layouts) = module_loading::embedded_names();
+    layouts.push(layout_manager::NAME.to_string());
+    layouts.push(flow_layout::NAME.to_string());
+    format!(
+        "{}\nfeatures: {}\nembedded modules: {}\nbuilt-in layouts: {}",
+        env!("CARGO_PKG_VERSION"),
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        },
+        if modules.is_empty() {
+            "none".to_string()
+        } else {
+            modules.join(", ")
+        },
+        layouts.join(", ")
+    )
+}
+
 fn detach(log_file_path: &Path) -> Result<Pid> {
     std::fs::create_dir_all(log_file_path.parent().expect("invalid log path"))?;
     let file = std::fs::OpenOptions::new()
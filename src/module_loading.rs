@@ -23,7 +23,7 @@ use tokio::sync::Mutex;
 
 use crate::{
     app::App,
-    layout_manager::{self, fallback_layout},
+    layout_manager::{self, fallback_layout, flow_layout},
 };
 
 impl App {
@@ -31,7 +31,12 @@ impl App {
         let mut module_order = vec![];
         let module_def_map = crate::module_loading::get_module_definitions(config_dir);
 
-        if self.config.loaded_modules.contains(&"all".to_string()) {
+        if self
+            .config
+            .loaded_modules
+            .iter()
+            .any(|module| module.name() == "all")
+        {
             //load all modules available in order of hash (random order)
             for module_def in module_def_map {
                 let module_name = module_def.0;
@@ -40,7 +45,7 @@ impl App {
                 let built_module = match module_constructor(self.app_send.clone().unwrap()) {
                     ROk(x) => x,
                     RErr(e) => {
-                        log::error!("error during creation of {module_name}: {e:#?}");
+                        log::error!(target: &module_log_target(module_name), "error during creation of {module_name}: {e:#?}");
                         continue;
                     }
                 };
@@ -52,11 +57,16 @@ impl App {
             }
         } else {
             //load only modules in the config in order of definition
-            for module_name in self.config.loaded_modules.iter() {
+            for module_entry in self.config.loaded_modules.iter() {
+                let module_name = module_entry.name();
+                if let Some(reason) = module_entry.unmet_condition() {
+                    log::info!(target: &module_log_target(module_name), "skipping module {module_name}: {reason}");
+                    continue;
+                }
                 let module_constructor = module_def_map.get(module_name);
                 let module_constructor = match module_constructor {
                     None => {
-                        log::warn!("module {} not found, skipping", module_name);
+                        log::warn!(target: &module_log_target(module_name), "module {} not found, skipping", module_name);
                         continue;
                     }
                     Some(x) => x,
@@ -65,7 +75,7 @@ impl App {
                 let built_module = match module_constructor(self.app_send.clone().unwrap()) {
                     ROk(x) => x,
                     RErr(e) => {
-                        log::error!("error during creation of {module_name}: {e:#?}");
+                        log::error!(target: &module_log_target(module_name), "error during creation of {module_name}: {e:#?}");
                         continue;
                     }
                 };
@@ -95,6 +105,12 @@ impl App {
             self.load_fallback_layout();
             return;
         }
+        if lm_name == flow_layout::NAME {
+            log::info!("using layout manager: {}", flow_layout::NAME);
+            let layout = flow_layout::new(self.application.clone().into()).unwrap();
+            self.layout = Some(Rc::new(Mutex::new((lm_name.clone(), layout))));
+            return;
+        }
         let lm_constructor = layout_manager_definitions.get(lm_name);
         let lm_constructor = match lm_constructor {
             None => {
@@ -131,6 +147,33 @@ impl App {
     }
 }
 
+/// Log target of the messages about a module, so they can be filtered with
+/// `RUST_LOG=dynisland::module::<module name>=debug`
+pub fn module_log_target(module_name: impl std::fmt::Display) -> String {
+    format!("dynisland::module::{module_name}")
+}
+
+/// Names of the modules and layout managers compiled into the binary with `embed_modules`
+pub fn embedded_names() -> (Vec<String>, Vec<String>) {
+    #[cfg(feature = "embed_modules")]
+    {
+        let modules: Vec<String> = vec![
+            clock_module::instantiate_root_module().name().into(),
+            music_module::instantiate_root_module().name().into(),
+            script_module::instantiate_root_module().name().into(),
+            systray_module::instantiate_root_module().name().into(),
+        ];
+        let layouts: Vec<String> = vec![dynamic_layoutmanager::instantiate_root_module()
+            .name()
+            .into()];
+        (modules, layouts)
+    }
+    #[cfg(not(feature = "embed_modules"))]
+    {
+        (Vec::new(), Vec::new())
+    }
+}
+
 pub fn get_module_definitions(
     _config_dir: &Path,
 ) -> HashMap<String, extern "C" fn(RSender<UIServerCommand>) -> RResult<ModuleType, RBoxError>> {
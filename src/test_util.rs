@@ -0,0 +1,46 @@
+//! Helpers for the tests that need gtk
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Mutex, OnceLock},
+    thread,
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs `f` on the thread where gtk was initialized, gtk can only be used from that one thread.
+/// Returns `None` if gtk can't be initialized (no display), so the test can be skipped
+pub(crate) fn with_gtk<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Option<T> {
+    static GTK_THREAD: OnceLock<Option<Mutex<mpsc::Sender<Job>>>> = OnceLock::new();
+    let Some(jobs) = GTK_THREAD.get_or_init(|| {
+        let (job_send, job_recv) = mpsc::channel::<Job>();
+        let (ready_send, ready_recv) = mpsc::channel();
+        thread::spawn(move || {
+            let initialized = gtk::init().is_ok();
+            let _ = ready_send.send(initialized);
+            if initialized {
+                for job in job_recv {
+                    job();
+                }
+            }
+        });
+        ready_recv
+            .recv()
+            .unwrap_or(false)
+            .then(|| Mutex::new(job_send))
+    }) else {
+        eprintln!("gtk can't be initialized, skipping the test");
+        return None;
+    };
+    let (result_send, result_recv) = mpsc::channel();
+    jobs.lock()
+        .unwrap()
+        .send(Box::new(move || {
+            let _ = result_send.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        }))
+        .expect("the gtk thread stopped");
+    match result_recv.recv().expect("the gtk thread stopped") {
+        Ok(result) => Some(result),
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}